@@ -50,6 +50,40 @@ pub enum Error {
     DuplicatedBeated,
     #[error("Internal: Cannot find next attacker")]
     CantFindNextAttacker,
+    #[error("Player not found: {0}")]
+    PlayerNotFound(String),
+    #[error("Insufficient balance: {0}")]
+    InsufficientBalance(u64),
+    #[error("The trump is already shown in this round")]
+    DuplicatedShownTrump,
+    #[error("Duplicated pass action")]
+    DuplicatedPass,
+    #[error("Duplicated rematch action")]
+    DuplicatedRematch,
+    #[error("Duplicated ready")]
+    DuplicatedReady,
+    #[error("Player is not ready")]
+    NotReady,
+    #[error("Invalid seat: {0}")]
+    InvalidSeat(u16),
+    #[error("Internal: Too many open attacks, cards in hand: {0}, open attacks: {1}")]
+    TooManyOpenAttacks(usize, usize),
+    #[error("Internal: Too many finished players, finished: {0}, players: {1}")]
+    TooManyFinishedPlayers(usize, usize),
+    #[error("Internal: Invariant violated, {0}")]
+    InvariantViolated(String),
+    #[error("The series is in progress")]
+    SeriesInProgress,
+    #[error("The tournament is in progress")]
+    TournamentInProgress,
+    #[error("Invalid game mode")]
+    InvalidGameMode,
+    #[error("Invalid payout rule")]
+    InvalidPayoutRule,
+    #[error("Invalid rake")]
+    InvalidRake,
+    #[error("Invalid shame rule")]
+    InvalidShameRule,
 }
 
 impl From<Error> for race_api::error::HandleError {
@@ -0,0 +1,409 @@
+//! An in-process fuzz harness.  Random event sequences and raw
+//! custom payloads are fed into a game with a mock effect, which
+//! reveals the cards of a known deck once they are requested and
+//! the secrets are ready.  The handler must never panic, and the
+//! invariants must hold after every event.  Like the
+//! transactor, the state is rolled back when an event is rejected,
+//! though a rejected action must not change anything already.  The
+//! system events are sent as the transactor does by the effects, so
+//! they must not be rejected, except a timeout dispatched in a stage
+//! which is already over.
+
+use std::collections::HashMap;
+
+use race_api::prelude::*;
+
+use crate::{
+    error::Error,
+    misc::{Action, Attack, Card, Defense, Role, Stage, DECK_LEN},
+    Durak,
+};
+
+const NUM_OF_SEEDS: u64 = 200;
+const NUM_OF_STEPS: usize = 400;
+const ADDRS: [&str; 4] = ["alice", "bob", "carol", "dave"];
+
+/// A xorshift generator, good enough to explore the states.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+struct Harness {
+    rng: Rng,
+    state: Durak,
+    deck: Vec<String>,
+    timestamp: u64,
+    /// The events the transactor would send next.
+    pending: Vec<Event>,
+    /// The timeout event the transactor would dispatch and when it is
+    /// due, each effect with a timeout replaces it.
+    dispatch: Option<(u64, Event)>,
+    /// Whether the players can leave, by the latest effect.
+    allow_exit: bool,
+    /// The node running the handler, it sees its own assigned cards
+    /// as a client does.
+    viewer: String,
+    /// The indexes requested by the effects, revealed with the next
+    /// secrets.
+    requested_idxs: Vec<usize>,
+    /// The indexes revealed of the current randomness.
+    revealed_idxs: Vec<usize>,
+}
+
+impl Harness {
+    fn new(seed: u64) -> Self {
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+        let mut deck: Vec<String> = ["s", "h", "d", "c"]
+            .iter()
+            .flat_map(|s| {
+                ["6", "7", "8", "9", "t", "j", "q", "k", "a"]
+                    .iter()
+                    .map(move |k| format!("{}{}", s, k))
+            })
+            .collect();
+        for i in (1..deck.len()).rev() {
+            let j = rng.below(i + 1);
+            deck.swap(i, j);
+        }
+        let num_of_seats = 2 + rng.below(3);
+        let viewer = ADDRS[rng.below(num_of_seats)].into();
+        let state = Durak {
+            num_of_players: num_of_seats,
+            num_of_seats,
+            min_num_of_players: 2 + rng.below(num_of_seats - 1),
+            bet_amount: 100,
+            ..Default::default()
+        };
+        Self {
+            rng,
+            state,
+            deck,
+            timestamp: 0,
+            pending: vec![],
+            dispatch: None,
+            allow_exit: true,
+            viewer,
+            requested_idxs: vec![],
+            revealed_idxs: vec![],
+        }
+    }
+
+    fn effect(&self) -> Effect {
+        let revealed: HashMap<usize, String> = self
+            .revealed_idxs
+            .iter()
+            .map(|i| (*i, self.deck[*i].clone()))
+            .collect();
+        Effect {
+            timestamp: self.timestamp,
+            curr_random_id: 1,
+            revealed: HashMap::from([(self.state.random_id, revealed)]),
+            allow_exit: self.allow_exit,
+            ..Default::default()
+        }
+    }
+
+    fn random_addr(&mut self) -> String {
+        if self.rng.chance(5) {
+            return "mallory".into();
+        }
+        ADDRS[self.rng.below(self.state.num_of_seats)].into()
+    }
+
+    /// A card from the hand of `addr` most of time, otherwise any
+    /// index with any value.
+    fn random_card(&mut self, addr: &str) -> Card {
+        let hand: Vec<usize> = self
+            .state
+            .players
+            .get(addr)
+            .map(|p| p.card_idxs().to_vec())
+            .unwrap_or_default();
+        if !hand.is_empty() && self.rng.chance(80) {
+            let idx = hand[self.rng.below(hand.len())];
+            return Card::new(idx, self.deck[idx].clone());
+        }
+        let idx = self.rng.below(DECK_LEN + 4);
+        let value = match self.rng.below(4) {
+            0 => "".into(),
+            1 => "\u{1f0a1}".into(),
+            _ => self.deck[self.rng.below(DECK_LEN)].clone(),
+        };
+        Card::new(idx, value)
+    }
+
+    fn random_cards(&mut self, addr: &str) -> Vec<Card> {
+        let n = self.rng.below(4);
+        (0..n).map(|_| self.random_card(addr)).collect()
+    }
+
+    fn random_action(&mut self, addr: &str) -> Action {
+        match self.rng.below(13) {
+            0 => Action::Attack {
+                cards: self.random_cards(addr),
+            },
+            1 => Action::CoAttack {
+                cards: self.random_cards(addr),
+            },
+            2 => Action::Defend {
+                card: self.random_card(addr),
+                target: self.rng.below(8) as u8,
+            },
+            3 => Action::Forward {
+                card: self.random_card(addr),
+            },
+            4 => Action::Take,
+            5 => Action::Beated,
+            6 => Action::Rematch,
+            7 => Action::Ready,
+            8 => Action::Unready,
+            9 => Action::ShowTrumpForward {
+                card: self.random_card(addr),
+            },
+            10 => Action::DefendMany {
+                defenses: (0..self.rng.below(4))
+                    .map(|_| Defense {
+                        card: self.random_card(addr),
+                        target: self.rng.below(4) as u8,
+                    })
+                    .collect(),
+            },
+            11 => Action::Pass,
+            _ => Action::RequestSeat {
+                position: self.rng.below(5) as u16,
+            },
+        }
+    }
+
+    /// A reasonable action for the current stage, to drive the game
+    /// to its end.
+    fn guided_action(&mut self) -> Option<(String, Action)> {
+        match self.state.stage {
+            Stage::Waiting => {
+                let unready: Vec<String> = self
+                    .state
+                    .players
+                    .values()
+                    .filter(|p| !p.is_ready())
+                    .map(|p| p.addr())
+                    .collect();
+                let addr = unready.get(self.rng.below(unready.len()))?.clone();
+                Some((addr, Action::Ready))
+            }
+            Stage::EndOfRound => {
+                let throwers: Vec<String> = self
+                    .state
+                    .players
+                    .values()
+                    .filter(|p| p.can_attack())
+                    .map(|p| p.addr())
+                    .collect();
+                let addr = throwers.get(self.rng.below(throwers.len()))?.clone();
+                Some((addr, Action::Pass))
+            }
+            Stage::EndOfGame => {
+                let addrs: Vec<String> = self.state.players.keys().cloned().collect();
+                let addr = addrs.get(self.rng.below(addrs.len()))?.clone();
+                Some((addr, Action::Rematch))
+            }
+            Stage::Acting => {
+                let att = self.state.get_player_by_role(Role::Attacker).ok()?.addr();
+                let def = self.state.get_player_by_role(Role::Defender).ok()?.addr();
+                let trump = self.state.trump.clone()?;
+                if self.state.attacks.is_empty() {
+                    let card = self.random_card(&att);
+                    return Some((att, Action::Attack { cards: vec![card] }));
+                }
+                if self.state.attacks.iter().all(Attack::is_closed) {
+                    return Some((att, Action::Beated));
+                }
+                let target = self.state.attacks.iter().position(Attack::is_open)?;
+                let hand: Vec<Card> = self.state.players[&def]
+                    .card_idxs()
+                    .iter()
+                    .map(|i| Card::new(*i, self.deck[*i].clone()))
+                    .collect();
+                let attack = &self.state.attacks[target];
+                let card = hand
+                    .into_iter()
+                    .find(|c| matches!(attack.can_be_closed_by(c, &trump), Ok(true)));
+                match card {
+                    Some(card) => Some((
+                        def,
+                        Action::Defend {
+                            card,
+                            target: target as u8,
+                        },
+                    )),
+                    None => Some((def, Action::Take)),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn random_event(&mut self) -> Event {
+        if !self.pending.is_empty() && self.rng.chance(70) {
+            return self.pending.remove(0);
+        }
+        if self.dispatch.is_some() && self.rng.chance(25) {
+            let (due, event) = self.dispatch.take().unwrap();
+            self.timestamp = self.timestamp.max(due);
+            return event;
+        }
+        if self.rng.chance(50) {
+            if let Some((sender, action)) = self.guided_action() {
+                let raw = action.try_to_vec().unwrap();
+                return Event::Custom { sender, raw };
+            }
+        }
+        match self.rng.below(8) {
+            0 => {
+                let sender = self.random_addr();
+                let raw = (0..self.rng.below(40))
+                    .map(|_| self.rng.next() as u8)
+                    .collect();
+                Event::Custom { sender, raw }
+            }
+            1 if self.state.stage == Stage::Waiting => self.sync_or_leave(),
+            _ => {
+                let sender = self.random_addr();
+                let raw = self.random_action(&sender).try_to_vec().unwrap();
+                Event::Custom { sender, raw }
+            }
+        }
+    }
+
+    /// Join with a new address, or leave with a seated one if the
+    /// exit is allowed.
+    fn sync_or_leave(&mut self) -> Event {
+        let addr = self.random_addr();
+        if self.state.players.contains_key(&addr) || self.state.waiting_players.contains_key(&addr)
+        {
+            if self.allow_exit && self.rng.chance(20) {
+                return Event::Leave { player_addr: addr };
+            }
+            let raw = self.random_action(&addr).try_to_vec().unwrap();
+            return Event::Custom { sender: addr, raw };
+        }
+        Event::Sync {
+            new_players: vec![PlayerJoin {
+                addr,
+                position: self.rng.below(self.state.num_of_seats) as u16,
+                balance: 100 + self.rng.below(300) as u64,
+                access_version: 1,
+                verify_key: "".into(),
+            }],
+            new_servers: vec![],
+            transactor_addr: "".into(),
+            access_version: 1,
+        }
+    }
+
+    /// Queue the events the transactor sends for the effect.
+    fn follow(&mut self, effect: &Effect) {
+        let now = effect.timestamp;
+        if effect.start_game {
+            self.dispatch = Some((now, Event::GameStart { access_version: 1 }));
+        } else if let Some(t) = effect.action_timeout.as_ref() {
+            let event = Event::ActionTimeout {
+                player_addr: t.player_addr.clone(),
+            };
+            self.dispatch = Some((now + t.timeout, event));
+        } else if let Some(t) = effect.wait_timeout {
+            self.dispatch = Some((now + t, Event::WaitingTimeout));
+        }
+        self.allow_exit = effect.allow_exit;
+        if !effect.init_random_states.is_empty() {
+            self.pending.push(Event::RandomnessReady { random_id: 1 });
+            self.requested_idxs.clear();
+            self.revealed_idxs.clear();
+        }
+        for r in effect.reveals.iter() {
+            self.requested_idxs.extend(r.indexes.iter());
+        }
+        for a in effect
+            .assigns
+            .iter()
+            .filter(|a| a.player_addr == self.viewer)
+        {
+            self.requested_idxs.extend(a.indexes.iter());
+        }
+        if !effect.assigns.is_empty() || !effect.reveals.is_empty() {
+            self.pending.push(Event::SecretsReady {
+                random_ids: vec![self.state.random_id],
+            });
+        }
+    }
+
+    fn step(&mut self, seed: u64, step: usize) {
+        let event = self.random_event();
+        // A timeout dispatched in a previous stage is rejected
+        let is_stale = matches!(event, Event::ActionTimeout { .. })
+            && !matches!(self.state.stage, Stage::Acting | Stage::EndOfRound);
+        let snapshot = self.state.try_to_vec().unwrap();
+        if matches!(event, Event::SecretsReady { .. }) {
+            self.revealed_idxs.append(&mut self.requested_idxs);
+        }
+        let mut effect = self.effect();
+        self.timestamp += 1_000;
+        match self.state.handle_event(&mut effect, event.clone()) {
+            Ok(()) => self.follow(&effect),
+            Err(e) => {
+                if let Err(Error::InvariantViolated(reason)) = self.state.check_invariants() {
+                    panic!("seed {}, step {}, {}: {}", seed, step, event, reason);
+                }
+                // Only the actions of players and the stale timeouts
+                // can be rejected
+                assert!(
+                    matches!(event, Event::Custom { .. }) || is_stale,
+                    "seed {}, step {}, {} is rejected: {}",
+                    seed,
+                    step,
+                    event,
+                    e
+                );
+                // A rejected event changes nothing but the displays
+                let mut state = Durak::try_from_slice(&snapshot).unwrap();
+                state.displays.clear();
+                assert!(
+                    state.try_to_vec().unwrap() == self.state.try_to_vec().unwrap(),
+                    "seed {}, step {}, {}: state changed by {}",
+                    seed,
+                    step,
+                    event,
+                    e
+                );
+                self.state = Durak::try_from_slice(&snapshot).unwrap();
+            }
+        }
+        if let Err(e) = self.state.check_invariants() {
+            panic!("seed {}, step {}, {}: {}", seed, step, event, e);
+        }
+    }
+}
+
+#[test]
+fn test_fuzz_events() {
+    for seed in 0..NUM_OF_SEEDS {
+        let mut harness = Harness::new(seed);
+        for step in 0..NUM_OF_STEPS {
+            harness.step(seed, step);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Invariants of the game state, they are checked after every
+//! handled event in debug builds.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    error::Error,
+    misc::{Attack, Role, Stage, DECK_LEN},
+    Durak,
+};
+
+impl Durak {
+    /// Check the invariants of the state, return the first violated
+    /// one.
+    pub fn check_invariants(&self) -> Result<(), Error> {
+        self.check_card_idxs()?;
+        self.check_roles()?;
+        self.check_attack_space()?;
+        self.check_num_of_finished()?;
+        Ok(())
+    }
+
+    /// The cards in hands and attacks are unique and dealt from the
+    /// deck.
+    fn check_card_idxs(&self) -> Result<(), Error> {
+        if self.deck_offset > DECK_LEN {
+            return Err(violated(format!(
+                "deck offset {} exceeds the deck",
+                self.deck_offset
+            )));
+        }
+        let in_hands = self.players.values().flat_map(|p| p.card_idxs().to_vec());
+        let in_attacks = self.attacks.iter().flat_map(Attack::card_idxs);
+        let mut seen = BTreeSet::new();
+        for idx in in_hands.chain(in_attacks) {
+            if idx >= self.deck_offset {
+                return Err(violated(format!(
+                    "card {} is not dealt, deck offset {}",
+                    idx, self.deck_offset
+                )));
+            }
+            if !seen.insert(idx) {
+                return Err(violated(format!("card {} is duplicated", idx)));
+            }
+        }
+        Ok(())
+    }
+
+    /// There's exactly one attacker and one defender in a game, and
+    /// a co-attacker when three or more players are in the round.
+    /// The players escaped in current round keep their roles until
+    /// the round ends, so they are still in the round.
+    fn check_roles(&self) -> Result<(), Error> {
+        if !matches!(
+            self.stage,
+            Stage::Dealing | Stage::Acting | Stage::EndOfRound
+        ) {
+            return Ok(());
+        }
+        let count = |role: Role| {
+            self.players
+                .values()
+                .filter(|p| p.role() == Some(role))
+                .count()
+        };
+        let num_in_round = self
+            .players
+            .values()
+            .filter(|p| p.rank().is_none() || p.role().is_some())
+            .count();
+        let expected = [
+            (Role::Attacker, 1),
+            (Role::Defender, 1),
+            (Role::CoAttacker, usize::from(num_in_round >= 3)),
+            (Role::Escaped, 0),
+        ];
+        for (role, n) in expected {
+            if count(role) != n {
+                return Err(violated(format!(
+                    "expect {} {:?}, got {}",
+                    n,
+                    role,
+                    count(role)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The attack space is the number of cards the defender can
+    /// still beat, once the attacks are revealed.
+    fn check_attack_space(&self) -> Result<(), Error> {
+        if self.stage != Stage::Acting || !self.is_all_attacks_confirmed() {
+            return Ok(());
+        }
+        let def = self
+            .get_player_by_role(Role::Defender)
+            .map_err(|_| violated("no defender".into()))?;
+        let num_of_open = self.attacks.iter().filter(|a| a.is_open()).count();
+        let expected = def.card_idxs().len().saturating_sub(num_of_open);
+        if self.attack_space != expected {
+            return Err(violated(format!(
+                "attack space {}, expect {}",
+                self.attack_space, expected
+            )));
+        }
+        Ok(())
+    }
+
+    /// The number of finished players equals to the ranked ones.
+    fn check_num_of_finished(&self) -> Result<(), Error> {
+        let num_of_ranked = self.players.values().filter(|p| p.rank().is_some()).count();
+        if self.num_of_finished != num_of_ranked {
+            return Err(violated(format!(
+                "{} players finished, but {} ranked",
+                self.num_of_finished, num_of_ranked
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn violated(reason: String) -> Error {
+    Error::InvariantViolated(reason)
+}
@@ -2,21 +2,42 @@ use std::collections::BTreeMap;
 
 use borsh::{BorshDeserialize, BorshSerialize};
 use error::Error;
-use misc::{Action, Attack, Card, Display, Player, Role, Stage, DECK_LEN};
+use misc::{
+    Action, Attack, Card, DeclaredCard, Display, GameMode, PayoutRule, Player, Rake, Role,
+    ShameRule, Stage, StakeRule, DECK_LEN,
+};
 use race_api::prelude::*;
 use race_proc_macro::game_handler;
 
 mod error;
+#[cfg(test)]
+mod fuzz;
+mod invariant;
 mod misc;
+mod spectator;
 
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct DurakAccount {
     pub bet_amount: u64,
     pub num_of_players: u8,
+    pub mode: GameMode,
+    pub payout_rule: PayoutRule,
+    pub rake: Option<Rake>,
+    pub shame_rule: Option<ShameRule>,
+    pub stake_rule: StakeRule,
+    /// The game can start with this number of players after a
+    /// countdown, zero means the seats must be full.
+    pub min_num_of_players: u8,
 }
 
-#[derive(BorshSerialize, BorshDeserialize)]
-pub struct DurakCheckpoint {}
+#[derive(Default, BorshSerialize, BorshDeserialize)]
+pub struct DurakCheckpoint {
+    pub num_of_played_games: u16,
+    pub penalty_points: BTreeMap<String, u16>,
+    pub eliminated_addrs: Vec<String>,
+    pub entrant_addrs: Vec<String>,
+    pub stake: u64,
+}
 
 #[game_handler]
 #[derive(Default, BorshSerialize, BorshDeserialize)]
@@ -29,10 +50,41 @@ pub struct Durak {
     pub players: BTreeMap<String, Player>,
     pub attacks: Vec<Attack>,
     pub trump: Option<Card>,
+    pub trump_holder: Option<String>,
     pub bet_amount: u64,
     pub timeout: u64,
     pub attack_space: usize,
     pub beated_addrs: Vec<String>,
+    pub rematch_addrs: Vec<String>,
+    pub mode: GameMode,
+    pub num_of_played_games: u16,
+    pub penalty_points: BTreeMap<String, u16>,
+    pub payout_rule: PayoutRule,
+    pub rake: Option<Rake>,
+    pub shame_rule: Option<ShameRule>,
+    pub stake_rule: StakeRule,
+    /// The stake of current game, decided by the stake rule.
+    pub stake: u64,
+    /// The players eliminated from the tournament, in order.
+    pub eliminated_addrs: Vec<String>,
+    /// The players who entered the series or the tournament.
+    pub entrant_addrs: Vec<String>,
+    /// The players joined after the game started, they are seated
+    /// for the next game.
+    pub waiting_players: BTreeMap<String, Player>,
+    pub num_of_seats: usize,
+    pub min_num_of_players: usize,
+    /// The trump shown to forward, it's waiting for the reveal.
+    pub showing_trump_idx: Option<usize>,
+    /// The trumps already shown in current round.
+    pub shown_trump_idxs: Vec<usize>,
+    /// The throwers who have no more cards to give after the take.
+    pub passed_addrs: Vec<String>,
+    /// The card to forward, it's waiting for the reveal.
+    pub forwarding_card: Option<Card>,
+    /// The cards played but not revealed yet, they are checked
+    /// against the declared values once revealed.
+    pub declared_cards: Vec<DeclaredCard>,
     pub displays: Vec<Display>,
 }
 
@@ -86,10 +138,39 @@ impl GameHandler for Durak {
 
     fn init_state(effect: &mut Effect, init_account: InitAccount) -> HandleResult<Self> {
         let a: DurakAccount = init_account.data()?;
-        effect.allow_exit(true);
+        if !a.mode.is_valid() {
+            return Err(Error::InvalidGameMode)?;
+        }
+        if !a.payout_rule.is_valid() {
+            return Err(Error::InvalidPayoutRule)?;
+        }
+        if matches!(a.rake, Some(r) if !r.is_valid()) {
+            return Err(Error::InvalidRake)?;
+        }
+        if matches!(&a.shame_rule, Some(r) if !r.is_valid()) {
+            return Err(Error::InvalidShameRule)?;
+        }
+        let min_num_of_players = match a.min_num_of_players {
+            0 => a.num_of_players,
+            n if (2..=a.num_of_players).contains(&n) => n,
+            _ => return Err(Error::InvalidNumOfPlayers)?,
+        };
+        let checkpoint: DurakCheckpoint = init_account.checkpoint()?.unwrap_or_default();
+        effect.allow_exit(checkpoint.num_of_played_games == 0);
         Ok(Self {
-            num_of_players: a.num_of_players as usize,
             bet_amount: a.bet_amount,
+            stake_rule: a.stake_rule,
+            mode: a.mode,
+            payout_rule: a.payout_rule,
+            rake: a.rake,
+            shame_rule: a.shame_rule,
+            num_of_played_games: checkpoint.num_of_played_games,
+            penalty_points: checkpoint.penalty_points,
+            eliminated_addrs: checkpoint.eliminated_addrs,
+            entrant_addrs: checkpoint.entrant_addrs,
+            stake: checkpoint.stake,
+            num_of_seats: a.num_of_players as usize,
+            min_num_of_players: min_num_of_players as usize,
             ..Default::default()
         })
     }
@@ -106,8 +187,10 @@ impl GameHandler for Durak {
             }
             Event::Sync { new_players, .. } => {
                 for p in new_players.iter() {
-                    self.players
-                        .insert(p.addr.clone(), Player::new(p.addr.clone(), p.position));
+                    if self.eliminated_addrs.contains(&p.addr) {
+                        continue;
+                    }
+                    self.add_player(Player::new(p.addr.clone(), p.position, p.balance));
                 }
                 self.try_start_game(effect);
             }
@@ -115,18 +198,20 @@ impl GameHandler for Durak {
                 let rnd_spec = get_deck();
                 effect.allow_exit(false);
                 self.random_id = effect.init_random_state(rnd_spec);
-                self.stage = Stage::Dealing;
+                // Nothing is dealt until the shuffled deck is ready
+                self.stage = Stage::Shuffling;
             }
             // Receive when the deck shuffling is ready.
             Event::RandomnessReady { .. } => {
                 self.reveal_trump(effect)?;
-                self.init_roles()?;
             }
             Event::SecretsReady { .. } => {
                 match &self.stage {
                     Stage::RevealingTrump => {
                         self.update_trump(effect)?;
-                        self.deal_cards(effect)?;
+                        self.init_roles()?;
+                        let order = self.drawing_order()?;
+                        self.deal_cards(&order, effect)?;
                     }
                     Stage::Dealing => {
                         self.ask_to_act(effect)?;
@@ -137,6 +222,9 @@ impl GameHandler for Durak {
                     Stage::EndOfRound => {
                         self.update_attacks(effect)?;
                     }
+                    Stage::RevealingHands => {
+                        self.reveal_hands_or_settle(effect)?;
+                    }
                     _ => {
                         return Err(Error::InvalidStage(self.stage))?;
                     }
@@ -145,21 +233,25 @@ impl GameHandler for Durak {
             // Player can only leave before the game starts.
             Event::Leave { player_addr } => {
                 self.players.remove(&player_addr);
+                self.waiting_players.remove(&player_addr);
+                self.rematch_addrs.retain(|addr| addr.ne(&player_addr));
                 effect.settle(Settle::eject(&player_addr));
                 effect.checkpoint();
+                // A queued player may take the seat
+                self.try_start_game(effect);
             }
             Event::ActionTimeout { .. } => {
                 if self.stage == Stage::Acting {
                     if self.attacks.is_empty() || self.attacks.iter().all(Attack::is_closed) {
                         let att = self.get_player_by_role(Role::Attacker)?;
-                        self.displays.push(Display::PlayerAction {
+                        self.displays.push(Display::TimeoutAction {
                             addr: att.addr(),
                             action: Action::Beated,
                         });
                         self.end_round(false, effect)?;
                     } else {
                         let def = self.get_player_by_role(Role::Defender)?;
-                        self.displays.push(Display::PlayerAction {
+                        self.displays.push(Display::TimeoutAction {
                             addr: def.addr(),
                             action: Action::Take,
                         });
@@ -171,16 +263,33 @@ impl GameHandler for Durak {
                     return Err(Error::InvalidStage(self.stage))?;
                 }
             }
-            Event::WaitingTimeout => {
-                self.reset(effect);
+            // The countdown to start the game without full seats
+            Event::WaitingTimeout if self.stage == Stage::Waiting => {
+                self.end_countdown(effect);
+            }
+            // The rematch phase after the game is over
+            Event::WaitingTimeout if self.stage == Stage::EndOfGame => {
+                if self.is_series_in_progress() || self.is_tournament_in_progress() {
+                    self.next_game(effect);
+                } else {
+                    self.end_rematch(effect);
+                }
             }
             _ => (),
         }
+        #[cfg(debug_assertions)]
+        self.check_invariants()?;
         Ok(())
     }
 
     fn into_checkpoint(self) -> HandleResult<Self::Checkpoint> {
-        Ok(DurakCheckpoint {})
+        Ok(DurakCheckpoint {
+            num_of_played_games: self.num_of_played_games,
+            penalty_points: self.penalty_points,
+            eliminated_addrs: self.eliminated_addrs,
+            entrant_addrs: self.entrant_addrs,
+            stake: self.stake,
+        })
     }
 }
 
@@ -188,4 +297,202 @@ impl GameHandler for Durak {
 mod tests {
     use super::*;
     use race_test::prelude::*;
+    use spectator::{SpectatorAttack, SpectatorView};
+
+    struct TestGame {
+        ctx: GameContext,
+        handler: TestHandler<Durak>,
+        players: Vec<TestClient>,
+        transactor: TestClient,
+    }
+
+    impl TestGame {
+        /// Create a game with `addrs` seated by their order, then
+        /// handle events until the first attacker is asked to act.
+        fn start(addrs: &[&str]) -> anyhow::Result<Self> {
+            let transactor = TestClient::transactor("transactor");
+            let account = TestGameAccountBuilder::default()
+                .set_transactor(&transactor)
+                .with_max_players(addrs.len() as _)
+                .with_data(DurakAccount {
+                    bet_amount: 100,
+                    num_of_players: addrs.len() as _,
+                    mode: GameMode::Single,
+                    payout_rule: PayoutRule::WinnerTakesAll,
+                    rake: None,
+                    shame_rule: None,
+                    stake_rule: StakeRule::Fixed,
+                    min_num_of_players: 0,
+                })
+                .build();
+            let access_version = account.access_version + 1;
+            let mut ctx = GameContext::try_new(&account)?;
+            let handler = TestHandler::<Durak>::init_state(&mut ctx, &account)?;
+            let players = addrs.iter().map(|a| TestClient::player(*a)).collect();
+            let mut game = Self {
+                ctx,
+                handler,
+                players,
+                transactor,
+            };
+            let new_players: Vec<(&str, u16, u64)> = addrs
+                .iter()
+                .enumerate()
+                .map(|(i, a)| (*a, i as u16, 100))
+                .collect();
+            game.handle(&sync_new_players(&new_players, access_version))?;
+            for addr in addrs {
+                game.act(addr, Action::Ready)?;
+            }
+            Ok(game)
+        }
+
+        fn handle(&mut self, event: &Event) -> anyhow::Result<()> {
+            let mut clients: Vec<&mut TestClient> = self.players.iter_mut().collect();
+            clients.push(&mut self.transactor);
+            self.handler
+                .handle_until_no_events(&mut self.ctx, event, clients)?;
+            Ok(())
+        }
+
+        fn state(&self) -> &Durak {
+            self.handler.get_state()
+        }
+
+        fn addr_by_role(&self, role: Role) -> String {
+            self.state()
+                .get_player_by_role(role)
+                .expect("No player found by role")
+                .addr()
+        }
+
+        fn client(&self, addr: &str) -> &TestClient {
+            self.players
+                .iter()
+                .find(|c| c.get_addr() == addr)
+                .expect("No client found")
+        }
+
+        /// Get the cards in hand of player `addr` with their values.
+        fn hand(&mut self, addr: &str) -> anyhow::Result<Vec<Card>> {
+            let random_id = self.state().random_id;
+            let idxs = self.state().players[addr].card_idxs().to_vec();
+            let client = self
+                .players
+                .iter_mut()
+                .find(|c| c.get_addr() == addr)
+                .expect("No client found");
+            let decrypted = client.decrypt(&self.ctx, random_id)?;
+            Ok(idxs
+                .into_iter()
+                .map(|i| Card::new(i, decrypted[&i].clone()))
+                .collect())
+        }
+
+        fn act(&mut self, addr: &str, action: Action) -> anyhow::Result<()> {
+            let event = self.client(addr).custom_event(action);
+            self.handle(&event)
+        }
+    }
+
+    #[test]
+    fn test_start_heads_up_game() -> anyhow::Result<()> {
+        let game = TestGame::start(&["alice", "bob"])?;
+        let state = game.state();
+        assert_eq!(state.stage, Stage::Acting);
+        assert_eq!(state.deck_offset, 12);
+        assert!(state.trump.is_some());
+        assert!(state.trump_holder.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spectator_view() -> anyhow::Result<()> {
+        let mut game = TestGame::start(&["alice", "bob"])?;
+        let att = game.addr_by_role(Role::Attacker);
+        let card = game.hand(&att)?.remove(0);
+        game.act(
+            &att,
+            Action::Attack {
+                cards: vec![card.clone()],
+            },
+        )?;
+
+        let view = game.state().spectator_view();
+        assert_eq!(view.stage, Stage::Acting);
+        assert_eq!(view.deck_size, DECK_LEN - 12);
+        assert_eq!(view.discard_size, 0);
+        assert_eq!(
+            view.trump,
+            game.state().trump.as_ref().map(|c| c.value.clone())
+        );
+        assert_eq!(
+            view.attacks,
+            vec![SpectatorAttack::Open { open: card.value }]
+        );
+        for p in view.players.iter() {
+            let expected = if p.addr == att { 5 } else { 6 };
+            assert_eq!(p.hand_size, expected);
+        }
+
+        let parsed = SpectatorView::try_from_slice(&view.try_to_vec()?)?;
+        assert_eq!(parsed, view);
+        Ok(())
+    }
+
+    #[test]
+    fn test_displays_on_action_timeout() -> anyhow::Result<()> {
+        let mut game = TestGame::start(&["alice", "bob"])?;
+        let att = game.addr_by_role(Role::Attacker);
+        let def = game.addr_by_role(Role::Defender);
+        game.handle(&Event::ActionTimeout {
+            player_addr: att.clone(),
+        })?;
+
+        let displays = &game.state().displays;
+        assert!(matches!(
+            &displays[0],
+            Display::TimeoutAction { addr, action: Action::Beated } if *addr == att
+        ));
+        assert!(matches!(
+            displays[1],
+            Display::RoundEnded {
+                attack_succeed: false
+            }
+        ));
+        assert!(displays.iter().any(|d| matches!(
+            d,
+            Display::RoleChanged { addr, role: Some(Role::Attacker) } if *addr == def
+        )));
+        assert!(displays.iter().any(|d| matches!(
+            d,
+            Display::RoleChanged { addr, role: Some(Role::Defender) } if *addr == att
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn test_trump_is_dealt_as_the_last_card() -> anyhow::Result<()> {
+        let mut game = TestGame::start(&["alice", "bob"])?;
+        // Leave only the trump in the deck
+        game.handler.get_mut_state().deck_offset = DECK_LEN - 1;
+        let att = game.addr_by_role(Role::Attacker);
+        let def = game.addr_by_role(Role::Defender);
+        let card = game.hand(&att)?.remove(0);
+        game.act(&att, Action::Attack { cards: vec![card] })?;
+        game.act(&def, Action::Take)?;
+        game.handle(&Event::ActionTimeout {
+            player_addr: def.clone(),
+        })?;
+
+        let state = game.state();
+        let trump = state.trump.as_ref().unwrap();
+        assert!(state.is_deck_empty());
+        assert_eq!(state.trump_holder.as_deref(), Some(att.as_str()));
+        let holder = &state.players[&att];
+        assert!(holder.card_idxs().contains(&trump.idx));
+        assert_eq!(holder.known_cards().len(), 1);
+        assert_eq!(holder.known_cards()[0].value, trump.value);
+        Ok(())
+    }
 }
@@ -1,4 +1,5 @@
-use std::mem::{replace, swap};
+use std::collections::HashMap;
+use std::mem::{replace, swap, take};
 
 use crate::{error::Error, Durak};
 use race_api::prelude::*;
@@ -12,6 +13,7 @@ const MAX_NUM_OF_PLAYERS: usize = 4;
 const ACT_TIMEOUT_MS: u64 = 20_000;
 const RESET_TIMEOUT_MS: u64 = 30_000;
 const END_OF_ROUND_TIMEOUT_MS: u64 = 5_000;
+const START_COUNTDOWN_MS: u64 = 15_000;
 
 fn kind_str_to_u8(k: &str) -> u8 {
     match k {
@@ -24,10 +26,29 @@ fn kind_str_to_u8(k: &str) -> u8 {
     }
 }
 
+/// The balance change of a player at the end of game.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct Payout {
+    pub addr: String,
+    pub change: i64,
+}
+
+/// Transitions of the latest event, used by the client to animate.
 #[derive(BorshSerialize, BorshDeserialize)]
 pub enum Display {
-    DealCadrs { addr: String, card_idxs: Vec<usize> },
+    DealCards { addr: String, card_idxs: Vec<usize> },
     PlayerAction { addr: String, action: Action },
+    TrumpDealt { addr: String, card: Card },
+    TrumpRevealed { card: Card },
+    RoundStarted,
+    RoundEnded { attack_succeed: bool },
+    RoleChanged { addr: String, role: Option<Role> },
+    PlayerEscaped { addr: String, rank: u8 },
+    GameOver { payouts: Vec<Payout> },
+    TimeoutAction { addr: String, action: Action },
+    HandRevealed { addr: String, cards: Vec<Card> },
+    TrumpShown { addr: String, card: Card },
+    PlayerKicked { addr: String },
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Clone)]
@@ -39,11 +60,13 @@ impl Card {
     pub fn new(idx: usize, value: String) -> Self {
         Self { idx, value }
     }
+    /// The suit, empty for a malformed value.
     pub fn suit(&self) -> &str {
-        &self.value[0..1]
+        self.value.get(0..1).unwrap_or_default()
     }
+    /// The kind, empty for a malformed value.
     pub fn kind(&self) -> &str {
-        &self.value[1..2]
+        self.value.get(1..2).unwrap_or_default()
     }
     pub fn is_same_suit(&self, other: &Card) -> bool {
         self.suit().eq(other.suit())
@@ -56,6 +79,14 @@ impl Card {
     }
 }
 
+/// A card played by `addr` before it's revealed, with the value the
+/// player declared for it.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct DeclaredCard {
+    pub addr: String,
+    pub card: Card,
+}
+
 #[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq, Eq, Copy, Clone)]
 pub enum Role {
     Attacker,
@@ -64,16 +95,183 @@ pub enum Role {
     Escaped,
 }
 
+/// How the stake of each game is decided.
+#[derive(Debug, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq, Copy, Clone)]
+pub enum StakeRule {
+    /// Every game is played for the bet amount.
+    #[default]
+    Fixed,
+    /// Every game is played for the smallest balance on the table,
+    /// the bet amount is the minimum.
+    MinBalance,
+}
+
+/// How the games on a table are settled.
+#[derive(Debug, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq, Copy, Clone)]
+pub enum GameMode {
+    /// Settle after every game.
+    #[default]
+    Single,
+    /// Play a series of games, the durak of each game gets a penalty
+    /// point.  The series ends after `num_of_games` games or when a
+    /// player reaches `target_points`, zero means no limit.
+    Series {
+        num_of_games: u16,
+        target_points: u16,
+    },
+    /// Sit-and-go tournament, players buy in once, and the durak of
+    /// each game is eliminated.  The prize pool is paid out by the
+    /// payout rule when only one player is left.
+    Tournament,
+}
+
+impl GameMode {
+    /// A series must end by either the number of games or points.
+    pub fn is_valid(&self) -> bool {
+        !matches!(
+            self,
+            GameMode::Series {
+                num_of_games: 0,
+                target_points: 0
+            }
+        )
+    }
+}
+
+/// How the bet paid by the durak is shared by the other players.
+#[derive(Debug, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone)]
+pub enum PayoutRule {
+    /// The winner receives all.
+    #[default]
+    WinnerTakesAll,
+    /// Every non-durak player receives an equal share.
+    EqualShares,
+    /// The non-durak players receive shares weighted by their
+    /// standings, the first weight is for the winner.  Missing
+    /// weights are zero.
+    RankWeighted(Vec<u16>),
+}
+
+impl PayoutRule {
+    /// The winner must always receive a share.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            PayoutRule::RankWeighted(weights) => matches!(weights.first(), Some(w) if *w > 0),
+            _ => true,
+        }
+    }
+
+    /// Split `amount` for `num_of_receivers` players in standing
+    /// order.  Each share is rounded down, and the remainder is
+    /// given one unit each to the higher standing receivers, so the
+    /// shares always sum to `amount`.
+    pub fn shares(&self, amount: u64, num_of_receivers: usize) -> Vec<u64> {
+        let mut weights: Vec<u64> = (0..num_of_receivers)
+            .map(|i| match self {
+                PayoutRule::WinnerTakesAll => u64::from(i == 0),
+                PayoutRule::EqualShares => 1,
+                PayoutRule::RankWeighted(weights) => weights.get(i).copied().unwrap_or(0) as u64,
+            })
+            .collect();
+        let mut total: u64 = weights.iter().sum();
+        if total == 0 {
+            if let Some(w) = weights.first_mut() {
+                *w = 1;
+                total = 1;
+            } else {
+                return vec![];
+            }
+        }
+        let mut shares: Vec<u64> = weights
+            .iter()
+            .map(|w| (amount as u128 * *w as u128 / total as u128) as u64)
+            .collect();
+        let mut remainder = amount - shares.iter().sum::<u64>();
+        for (share, w) in shares.iter_mut().zip(weights) {
+            if remainder == 0 {
+                break;
+            }
+            if w > 0 {
+                *share += 1;
+                remainder -= 1;
+            }
+        }
+        shares
+    }
+}
+
+/// The house fee taken from the winnings, transferred to the
+/// recipient slot `slot_id`.
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq, Eq, Copy, Clone)]
+pub struct Rake {
+    pub slot_id: u8,
+    /// Fee rate in basis points, 100 is 1%.
+    pub bps: u16,
+    /// The max fee of a settlement, zero means no cap.
+    pub cap: u64,
+}
+
+impl Rake {
+    pub fn is_valid(&self) -> bool {
+        self.bps <= 10_000
+    }
+
+    /// Return the fee for `amount`, rounded down.
+    pub fn fee(&self, amount: u64) -> u64 {
+        let fee = (amount as u128 * self.bps as u128 / 10_000) as u64;
+        if self.cap > 0 {
+            fee.min(self.cap)
+        } else {
+            fee
+        }
+    }
+}
+
+/// The "pogony" rule, the loss of the durak is multiplied when he
+/// is left holding cards of `kinds` at the end of game.
+#[derive(Debug, BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone)]
+pub struct ShameRule {
+    /// Card kinds, e.g. "6".
+    pub kinds: Vec<String>,
+    pub max_multiplier: u8,
+}
+
+impl ShameRule {
+    pub fn is_valid(&self) -> bool {
+        self.max_multiplier >= 1
+    }
+
+    /// One for a clean loss, plus one for each shameful card,
+    /// capped by the max multiplier.
+    pub fn multiplier(&self, cards: &[Card]) -> u64 {
+        let cnt = cards
+            .iter()
+            .filter(|c| self.kinds.iter().any(|k| k == c.kind()))
+            .count() as u64;
+        (1 + cnt).min(self.max_multiplier as u64)
+    }
+}
+
 #[derive(Debug, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq, Copy, Clone)]
 pub enum Stage {
     #[default]
     Waiting,
+    /// The deck is being shuffled, no cards are dealt and no roles
+    /// are assigned until the trump is revealed.
     Shuffling,
     RevealingTrump,
     Dealing,
     Acting,
     EndOfRound,
     EndOfGame,
+    RevealingHands,
+}
+
+/// A card to close the attack at `target`.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct Defense {
+    pub card: Card,
+    pub target: u8,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Clone)]
@@ -93,6 +291,27 @@ pub enum Action {
     },
     Take,
     Beated,
+    Rematch,
+    Ready,
+    Unready,
+    /// Take the seat at `position` if it's free, otherwise request
+    /// to swap seats with its owner.  The swap is made when the
+    /// owner requests the seat of the sender in return.
+    RequestSeat {
+        position: u16,
+    },
+    /// Forward by showing a trump of the attacked kind, the card is
+    /// revealed for verification and kept in hand.
+    ShowTrumpForward {
+        card: Card,
+    },
+    /// Close several attacks at once, the cards are revealed
+    /// together.
+    DefendMany {
+        defenses: Vec<Defense>,
+    },
+    /// No more cards to throw in after the defender takes.
+    Pass,
 }
 impl CustomEvent for Action {}
 
@@ -103,23 +322,46 @@ pub struct Player {
     role: Option<Role>,
     position: u16,
     rank: Option<u8>,
+    known_cards: Vec<Card>,
+    balance: u64,
+    ready: bool,
+    seat_request: Option<u16>,
+    /// When the player is kicked if still not ready, 0 if no
+    /// countdown is running for the player.
+    idle_deadline: u64,
 }
 
 impl Player {
-    pub fn new(addr: String, position: u16) -> Self {
+    pub fn new(addr: String, position: u16, balance: u64) -> Self {
         Self {
             addr,
             card_idxs: vec![],
             position,
             role: None,
             rank: None,
+            known_cards: vec![],
+            balance,
+            ready: false,
+            seat_request: None,
+            idle_deadline: 0,
         }
     }
+    /// Clear the states of last game, keep the seat and balance.
+    pub fn reset(&mut self) {
+        self.card_idxs.clear();
+        self.role = None;
+        self.rank = None;
+        self.known_cards.clear();
+        self.ready = false;
+        self.seat_request = None;
+        self.idle_deadline = 0;
+    }
     pub fn addr(&self) -> String {
         self.addr.clone()
     }
     pub fn take_card(&mut self, card_idx: usize) -> HandleResult<usize> {
         if let Some(p) = self.card_idxs.iter().position(|i| *i == card_idx) {
+            self.known_cards.retain(|c| c.idx != card_idx);
             Ok(self.card_idxs.remove(p))
         } else {
             Err(Error::InvalidCardIndex(card_idx))?
@@ -128,6 +370,10 @@ impl Player {
     pub fn card_idxs(&self) -> &[usize] {
         &self.card_idxs
     }
+    /// The cards in hand whose values are known to everyone.
+    pub fn known_cards(&self) -> &[Card] {
+        &self.known_cards
+    }
     pub fn is_attacker(&self) -> bool {
         self.role == Some(Role::Attacker)
     }
@@ -146,6 +392,29 @@ impl Player {
     pub fn rank(&self) -> Option<u8> {
         self.rank
     }
+    pub fn role(&self) -> Option<Role> {
+        self.role
+    }
+    pub fn position(&self) -> u16 {
+        self.position
+    }
+    pub fn balance(&self) -> u64 {
+        self.balance
+    }
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+    pub fn seat_request(&self) -> Option<u16> {
+        self.seat_request
+    }
+    pub fn idle_deadline(&self) -> u64 {
+        self.idle_deadline
+    }
+    /// Whether the countdown of the player ended before `timestamp`
+    /// and the player is still not ready.
+    pub fn is_idle_at(&self, timestamp: u64) -> bool {
+        !self.ready && self.idle_deadline != 0 && self.idle_deadline <= timestamp
+    }
 }
 
 #[derive(BorshSerialize, BorshDeserialize)]
@@ -200,6 +469,18 @@ impl Attack {
         Ok(())
     }
 
+    /// Take back the card closing the attack before it's revealed.
+    pub fn reopen(&mut self) -> HandleResult<()> {
+        match self {
+            Attack::ConfirmClose { open, .. } => {
+                let open = open.clone();
+                let _ = replace(self, Attack::Open { open });
+            }
+            _ => Err(Error::InvalidAttackStatus)?,
+        }
+        Ok(())
+    }
+
     pub fn close(&mut self, card: &Card) -> HandleResult<()> {
         match self {
             Attack::Open { open } => {
@@ -220,14 +501,14 @@ impl Attack {
     pub fn can_be_closed_by(&self, card: &Card, trump: &Card) -> HandleResult<bool> {
         match self {
             Attack::Open { open } => {
-                if open.is_same_suit(&trump) {
+                if open.is_same_suit(trump) {
                     // Trump suit can only be closed by trump suit
-                    Ok(open.is_covered_by(&card))
-                } else if card.is_same_suit(&trump) {
+                    Ok(open.is_covered_by(card))
+                } else if card.is_same_suit(trump) {
                     // Trump suit can always close non-trump suit
                     Ok(true)
                 } else {
-                    Ok(open.is_covered_by(&card))
+                    Ok(open.is_covered_by(card))
                 }
             }
             _ => Err(Error::InvalidAttackStatus)?,
@@ -243,6 +524,16 @@ impl Attack {
         }
     }
 
+    /// The indexes of all cards, including those waiting to be revealed.
+    pub fn card_idxs(&self) -> Vec<usize> {
+        match self {
+            Attack::ConfirmOpen { open_idx } => vec![*open_idx],
+            Attack::Open { open } => vec![open.idx],
+            Attack::ConfirmClose { open, close_idx } => vec![open.idx, *close_idx],
+            Attack::Closed { open, close } => vec![open.idx, close.idx],
+        }
+    }
+
     pub fn into_cards(self) -> HandleResult<Vec<Card>> {
         match self {
             Attack::Open { open } => Ok(vec![open]),
@@ -252,35 +543,286 @@ impl Attack {
     }
 }
 
+/// Assign attacker, defender and co-attacker to the players by
+/// their order, return the displays for the changed roles.
+fn assign_roles_in_order(players_in_order: Vec<&mut Player>) -> Vec<Display> {
+    let roles = [
+        Some(Role::Attacker),
+        Some(Role::Defender),
+        Some(Role::CoAttacker),
+    ];
+    let mut displays = vec![];
+    for (i, p) in players_in_order.into_iter().enumerate() {
+        let role = roles.get(i).copied().flatten();
+        if p.role != role {
+            p.role = role;
+            displays.push(Display::RoleChanged {
+                addr: p.addr.clone(),
+                role,
+            });
+        }
+    }
+    displays
+}
+
 impl Durak {
-    /// Reset game state to prepare for next game.
+    /// Reset game state to prepare for next game.  The players who
+    /// are still seated will play the next game.
     pub fn reset(&mut self, effect: &mut Effect) {
         self.random_id = 0;
         self.deck_offset = 0;
         self.stage = Stage::Waiting;
-        self.players.clear();
+        for p in self.players.values_mut() {
+            p.reset();
+        }
         self.attacks.clear();
         self.trump = None;
+        self.trump_holder = None;
         self.num_of_finished = 0;
         self.timeout = 0;
         self.attack_space = 0;
         self.displays.clear();
         self.beated_addrs.clear();
-        effect.allow_exit(true);
+        self.rematch_addrs.clear();
+        self.showing_trump_idx = None;
+        self.shown_trump_idxs.clear();
+        self.passed_addrs.clear();
+        self.forwarding_card = None;
+        self.declared_cards.clear();
+        // Nobody can leave a series or a tournament before it's settled
+        effect.allow_exit(!self.is_series_in_progress() && !self.is_tournament_in_progress());
+    }
+
+    /// Start the next game of the series or the tournament with the
+    /// same players, the eliminated players are removed.
+    pub fn next_game(&mut self, effect: &mut Effect) {
+        for addr in self.eliminated_addrs.iter() {
+            self.players.remove(addr);
+        }
+        self.reset(effect);
+        // The players are still in the series or the tournament
+        for p in self.players.values_mut() {
+            p.ready = true;
+        }
+        self.try_start_game(effect);
+    }
+
+    /// Finish the rematch phase.  Eject the players who didn't ask
+    /// for a rematch, the others are ready for the next game.
+    pub fn end_rematch(&mut self, effect: &mut Effect) {
+        let rematch_addrs = &self.rematch_addrs;
+        let declined: Vec<String> = self
+            .players
+            .keys()
+            .filter(|addr| !rematch_addrs.contains(addr))
+            .cloned()
+            .collect();
+        for addr in declined.iter() {
+            self.players.remove(addr);
+            effect.settle(Settle::eject(addr));
+        }
+        if !declined.is_empty() {
+            effect.checkpoint();
+        }
+        self.reset(effect);
+        for p in self.players.values_mut() {
+            p.ready = true;
+        }
+        self.try_start_game(effect);
+    }
+
+    /// Seat a new player.  The active players are never changed
+    /// after the game, the series or the tournament started, so the
+    /// late joiners are queued for the next game, so are the players
+    /// who find the table full.  The survivors of the series or the
+    /// tournament take their seats back, e.g. after the game is
+    /// restored from the checkpoint.
+    pub fn add_player(&mut self, mut player: Player) {
+        let is_in_progress = self.is_series_in_progress() || self.is_tournament_in_progress();
+        let is_seatable = !is_in_progress || self.is_survivor(&player.addr);
+        if self.stage == Stage::Waiting && is_seatable {
+            if let Some(position) = self.free_seat(player.position) {
+                player.position = position;
+                self.players.insert(player.addr(), player);
+                return;
+            }
+        }
+        self.waiting_players.insert(player.addr(), player);
+    }
+
+    /// Whether `addr` entered the series or the tournament, and is
+    /// not eliminated.
+    pub fn is_survivor(&self, addr: &str) -> bool {
+        self.entrant_addrs.iter().any(|a| a == addr)
+            && !self.eliminated_addrs.iter().any(|a| a == addr)
+    }
+
+    /// Seat the queued players for the next game.
+    pub fn seat_waiting_players(&mut self) {
+        for (_, player) in take(&mut self.waiting_players) {
+            self.add_player(player);
+        }
     }
 
-    /// Try start the game when there's enough players.
+    /// Try start the game when all seats are taken and all players
+    /// are ready.  Otherwise each player who is not ready has a
+    /// countdown since being seated or unready, and is kicked when it
+    /// ends.  If all players are ready but some seats are free, the
+    /// game starts short-handed at the end of another countdown.  The
+    /// queued players are seated first.
     pub fn try_start_game(&mut self, effect: &mut Effect) {
-        if self.players.len() == self.num_of_players {
-            effect.start_game();
-            self.stage = Stage::Shuffling;
+        if self.stage != Stage::Waiting {
+            return;
+        }
+        self.seat_waiting_players();
+        let n = self.players.len();
+        // The remaining players of the tournament must be all present
+        if self.is_tournament_in_progress() {
+            let is_all_present = self
+                .entrant_addrs
+                .iter()
+                .filter(|a| self.is_survivor(a))
+                .all(|a| self.players.contains_key(a));
+            if is_all_present {
+                self.start_game(effect);
+            }
+            return;
+        }
+        let now = effect.timestamp();
+        for p in self.players.values_mut() {
+            if !p.ready && p.idle_deadline == 0 {
+                p.idle_deadline = now + START_COUNTDOWN_MS;
+            }
+        }
+        let is_all_ready = self.players.values().all(Player::is_ready);
+        // Nobody else can join the series, no need to wait for them
+        let is_full = n >= self.num_of_seats || self.is_series_in_progress();
+        if is_all_ready && n >= self.min_num_of_players {
+            if is_full {
+                self.start_game(effect);
+                return;
+            }
+            if self.timeout == 0 {
+                self.timeout = now + START_COUNTDOWN_MS;
+            }
+        }
+        // Wake up at the nearest end of the countdowns
+        let deadline = self
+            .players
+            .values()
+            .filter(|p| !p.ready)
+            .map(Player::idle_deadline)
+            .chain([self.timeout])
+            .filter(|t| *t != 0)
+            .min();
+        if let Some(deadline) = deadline {
+            effect.wait_timeout(deadline.saturating_sub(now));
+        }
+    }
+
+    /// Kick the players whose countdowns ended and who are still
+    /// not ready.  The game is due to start once they are kicked or
+    /// the start countdown ends, even if some seats are free, as
+    /// long as the others are ready.
+    pub fn end_countdown(&mut self, effect: &mut Effect) {
+        let now = effect.timestamp();
+        let idle: Vec<String> = self
+            .players
+            .values()
+            .filter(|p| p.is_idle_at(now))
+            .map(Player::addr)
+            .collect();
+        let mut is_start_due = !idle.is_empty();
+        if !idle.is_empty() {
+            self.kick_players(&idle, effect);
+        }
+        if self.timeout != 0 && self.timeout <= now {
+            self.timeout = 0;
+            is_start_due = true;
+        }
+        if is_start_due && self.players.values().all(Player::is_ready) {
+            self.start_with_remaining(effect);
+        } else {
+            self.try_start_game(effect);
+        }
+    }
+
+    /// Start the game when it is due with the remaining players if
+    /// there are still enough of them, otherwise wait for more.
+    fn start_with_remaining(&mut self, effect: &mut Effect) {
+        let is_tournament = self.is_tournament_in_progress();
+        if !is_tournament && self.players.len() >= self.min_num_of_players {
+            self.start_game(effect);
+        } else {
+            self.try_start_game(effect);
+        }
+    }
+
+    /// Start the game with the seated players.  The players who
+    /// can't cover the stake are kicked, and the others try again.
+    fn start_game(&mut self, effect: &mut Effect) {
+        let Some(stake) = self.next_stake() else {
+            let underfunded: Vec<String> = self
+                .players
+                .values()
+                .filter(|p| p.balance < self.bet_amount)
+                .map(Player::addr)
+                .collect();
+            if !underfunded.is_empty() {
+                self.kick_players(&underfunded, effect);
+                self.start_with_remaining(effect);
+            }
+            return;
+        };
+        self.stake = stake;
+        self.num_of_players = self.players.len();
+        if !self.is_series_in_progress() && !self.is_tournament_in_progress() {
+            self.entrant_addrs = self.players.keys().cloned().collect();
+        }
+        self.timeout = 0;
+        effect.start_game();
+        self.stage = Stage::Shuffling;
+    }
+
+    /// Eject the seated players of `addrs` and tell the others.
+    fn kick_players(&mut self, addrs: &[String], effect: &mut Effect) {
+        for addr in addrs.iter() {
+            self.players.remove(addr);
+            effect.settle(Settle::eject(addr));
+            self.displays.push(Display::PlayerKicked {
+                addr: addr.to_owned(),
+            });
+        }
+        effect.checkpoint();
+    }
+
+    /// Return the stake of the next game by the stake rule, or None
+    /// if any player can't cover the bet amount.
+    pub fn next_stake(&self) -> Option<u64> {
+        // The buy-in is kept for the whole tournament
+        if self.is_tournament_in_progress() {
+            return Some(self.stake);
+        }
+        self.stake_for(self.players.values().map(|p| p.balance))
+    }
+
+    /// Return the stake of a game played by the players with
+    /// `balances`, or None if any of them can't cover the bet amount.
+    fn stake_for(&self, balances: impl Iterator<Item = u64>) -> Option<u64> {
+        let min_balance = balances.min()?;
+        if min_balance < self.bet_amount {
+            return None;
+        }
+        match self.stake_rule {
+            StakeRule::Fixed => Some(self.bet_amount),
+            StakeRule::MinBalance => Some(min_balance),
         }
     }
 
     /// Return a vector of mutable player references in acting order
     /// which starts from who has the `role`.
     pub fn players_in_acting_order_mut(&mut self, role: Role) -> HandleResult<Vec<&mut Player>> {
-        let pos = self.get_player_by_role(role)?.position.clone();
+        let pos = self.get_player_by_role(role)?.position;
         let mut players: Vec<&mut Player> = self.players.values_mut().collect();
         players.sort_by_key(|p| {
             if p.position >= pos {
@@ -313,19 +855,39 @@ impl Durak {
         Ok(players)
     }
 
-    /// Initialize the roles for players.
+    /// Return the lowest free seat, prefer the `position` if it's
+    /// free.  Return None if the table is full.
+    pub fn free_seat(&self, position: u16) -> Option<u16> {
+        let is_free = |pos: u16| {
+            (pos as usize) < self.num_of_seats && self.players.values().all(|p| p.position != pos)
+        };
+        if is_free(position) {
+            return Some(position);
+        }
+        (0..self.num_of_seats as u16).find(|pos| is_free(*pos))
+    }
+
+    /// Return the index of the first attacker in position order.  It
+    /// is picked by the trump card, which is randomized by the
+    /// shuffling and verifiable by everyone.
+    pub fn first_attacker_index(&self) -> HandleResult<usize> {
+        if self.players.is_empty() {
+            Err(Error::EmptyPlayers)?
+        }
+        let trump = self.get_trump()?;
+        let suit = "cdhs".find(trump.suit()).unwrap_or(0);
+        let kind = kind_str_to_u8(trump.kind()) as usize;
+        Ok((suit * 13 + kind) % self.players.len())
+    }
+
+    /// Initialize the roles for players, starting from the first
+    /// attacker picked by the trump.
     pub fn init_roles(&mut self) -> HandleResult<()> {
+        let first = self.first_attacker_index()?;
         let mut players_in_order = self.players_in_position_order_mut()?;
-        players_in_order
-            .get_mut(0)
-            .map(|p| p.role = Some(Role::Attacker));
-        players_in_order
-            .get_mut(1)
-            .map(|p| p.role = Some(Role::Defender));
-        players_in_order
-            .get_mut(2)
-            .map(|p| p.role = Some(Role::CoAttacker));
-        players_in_order.get_mut(3).map(|p| p.role = None);
+        players_in_order.rotate_left(first);
+        let mut displays = assign_roles_in_order(players_in_order);
+        self.displays.append(&mut displays);
         Ok(())
     }
 
@@ -352,21 +914,13 @@ impl Durak {
                     .ok_or(Error::CantFindNextAttacker)?
             }
         };
-        let mut players_in_order: Vec<&mut Player> = self
+        let players_in_order: Vec<&mut Player> = self
             .players_in_acting_order_mut(role)?
             .into_iter()
             .filter(|p| p.rank().is_none())
             .collect();
-        players_in_order
-            .get_mut(0)
-            .map(|p| p.role = Some(Role::Attacker));
-        players_in_order
-            .get_mut(1)
-            .map(|p| p.role = Some(Role::Defender));
-        players_in_order
-            .get_mut(2)
-            .map(|p| p.role = Some(Role::CoAttacker));
-        players_in_order.get_mut(3).map(|p| p.role = None);
+        let mut displays = assign_roles_in_order(players_in_order);
+        self.displays.append(&mut displays);
         Ok(())
     }
 
@@ -399,10 +953,225 @@ impl Durak {
             .is_some()
     }
 
+    /// Check the attack by `sender` in `role` with `cards`, before
+    /// anything is changed.  Each card must be in the hand, and be
+    /// given only once.
+    pub fn check_attack(&self, sender: &str, role: Role, cards: &[Card]) -> HandleResult<()> {
+        let l = cards.len();
+        if self.attack_space < l {
+            Err(Error::NoAttackSpace(self.attack_space, l))?
+        }
+        if !self.can_attack()? {
+            Err(Error::CantAttack)?
+        }
+        // Only the attacker can start the attacks
+        let is_first = role == Role::Attacker && self.attacks.is_empty();
+        if !(is_first || cards.iter().any(|c| self.is_valid_attack_card(c))) {
+            Err(Error::NotValidAttackCard)?
+        }
+        let p = self.get_player_by_role(role)?;
+        if p.addr.ne(sender) {
+            if role == Role::Attacker {
+                Err(Error::PlayerIsNotAttacker)?
+            } else {
+                Err(Error::PlayerIsNotCoAttacker)?
+            }
+        }
+        for (i, c) in cards.iter().enumerate() {
+            if !p.card_idxs.contains(&c.idx) || cards[..i].iter().any(|o| o.idx == c.idx) {
+                Err(Error::InvalidCardIndex(c.idx))?
+            }
+        }
+        Ok(())
+    }
+
+    /// Place `cards` as new attacks by the player in `role`, they
+    /// must be checked by `check_attack` first.  Return the card
+    /// indexes to reveal.
+    pub fn add_attacks(&mut self, role: Role, cards: &[Card]) -> HandleResult<Vec<usize>> {
+        let p = self.get_player_by_role_mut(role)?;
+        let mut idxs = vec![];
+        for c in cards.iter() {
+            idxs.push(p.take_card(c.idx)?);
+        }
+        self.attacks.extend(idxs.iter().copied().map(Attack::new));
+        Ok(idxs)
+    }
+
+    /// Check the defend by `sender` with `card` on the attack at
+    /// `target`, before anything is changed.
+    pub fn check_defend(&self, sender: &str, card: &Card, target: u8) -> HandleResult<()> {
+        if !self.can_defend()? {
+            Err(Error::CantDefend)?
+        }
+        let def = self.get_player_by_role(Role::Defender)?;
+        if def.addr.ne(sender) {
+            Err(Error::PlayerIsNotDefender)?
+        }
+        if !def.card_idxs.contains(&card.idx) {
+            Err(Error::InvalidCardIndex(card.idx))?
+        }
+        let a = self.get_attack(target)?;
+        if !a.can_be_closed_by(card, self.get_trump()?)? {
+            Err(Error::InvalidDefendCard)?
+        }
+        Ok(())
+    }
+
+    /// Check the defenses by `sender` together, before anything is
+    /// changed.  Each card and each target can be used only once.
+    pub fn check_defenses(&self, sender: &str, defenses: &[Defense]) -> HandleResult<()> {
+        if defenses.is_empty() {
+            Err(Error::InvalidDefendCard)?
+        }
+        for (i, d) in defenses.iter().enumerate() {
+            self.check_defend(sender, &d.card, d.target)?;
+            let prev = &defenses[..i];
+            if prev.iter().any(|p| p.card.idx == d.card.idx) {
+                Err(Error::InvalidCardIndex(d.card.idx))?
+            }
+            if prev.iter().any(|p| p.target == d.target) {
+                Err(Error::InvalidAttackIndex(d.target))?
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether every thrower who still has cards has passed, or no
+    /// more cards can be thrown anyway.
+    pub fn is_all_throwers_passed(&self) -> HandleResult<bool> {
+        if !self.can_attack()? {
+            return Ok(true);
+        }
+        Ok(self
+            .players
+            .values()
+            .filter(|p| p.can_attack() && !p.card_idxs.is_empty())
+            .all(|p| self.passed_addrs.contains(&p.addr)))
+    }
+
+    /// Return the player who defends after the forward.  The defender
+    /// attacks next, unless he escapes by the forward.  Return `None`
+    /// if there will be nobody to defend.
+    pub fn next_defender_on_forward(&self, escaping: bool) -> HandleResult<Option<&Player>> {
+        let pos = self.get_player_by_role(Role::Defender)?.position;
+        let mut players: Vec<&Player> = self
+            .players
+            .values()
+            .filter(|p| p.rank().is_none() && p.position != pos)
+            .collect();
+        players.sort_by_key(|p| {
+            if p.position > pos {
+                p.position
+            } else {
+                p.position + MAX_NUM_OF_PLAYERS as u16
+            }
+        });
+        Ok(players.get(usize::from(escaping)).copied())
+    }
+
+    /// Check the forward by `sender` with `card`, before anything is
+    /// changed.  The card is placed as a new attack, or only shown if
+    /// `place_card` is false, in which case it must be a trump.
+    ///
+    /// The conditions to check:
+    /// - Current stage is acting, and the sender is the defender
+    /// - All attacks are open, and having the same kind as the card
+    /// - The next defender has enough cards for all attacks
+    pub fn check_forward(&self, sender: &str, card: &Card, place_card: bool) -> HandleResult<()> {
+        if !self.can_defend()? {
+            Err(Error::CantForward)?
+        }
+        let def = self.get_player_by_role(Role::Defender)?;
+        if def.addr.ne(sender) {
+            Err(Error::PlayerIsNotDefender)?
+        }
+        if !def.card_idxs.contains(&card.idx) {
+            Err(Error::InvalidCardIndex(card.idx))?
+        }
+        let mut kinds = vec![];
+        for att in self.attacks.iter() {
+            match att {
+                Attack::Open { open } => kinds.push(open.kind()),
+                _ => Err(Error::CantForward)?,
+            }
+        }
+        if !kinds.windows(2).all(|w| w[0] == w[1]) {
+            Err(Error::CantForward)?
+        }
+        if !self.is_valid_attack_card(card) {
+            Err(Error::InvalidForwardCard)?
+        }
+        if !place_card && !self.is_trump_suit(card)? {
+            Err(Error::InvalidForwardCard)?
+        }
+        let num_of_attacks = self.attacks.len() + usize::from(place_card);
+        if num_of_attacks > MAX_ATTACK_COUNT {
+            Err(Error::CantForward)?
+        }
+        let escaping = place_card && self.is_deck_empty() && def.card_idxs.len() == 1;
+        if let Some(next) = self.next_defender_on_forward(escaping)? {
+            if next.card_idxs.len() < num_of_attacks {
+                Err(Error::CantForward)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Forward the attacks to the next player, it must be checked by
+    /// `check_forward` first.  The current defender becomes the
+    /// attacker, and others take the roles accordingly.
+    pub fn forward(&mut self, card: &Card, place_card: bool) -> HandleResult<()> {
+        if place_card {
+            let def = self.get_player_by_role_mut(Role::Defender)?;
+            let idx = def.take_card(card.idx)?;
+            self.attacks.push(Attack::new(idx));
+            // The defender may escape due to forward
+            self.update_escaped_players()?;
+        }
+        // Nobody to forward to, the game ends once the card is revealed
+        if self.num_of_remaining_players()? <= 1 {
+            return Ok(());
+        }
+        self.rotate_roles(false)?;
+        self.remove_roles_for_escaped_players();
+        Ok(())
+    }
+
+    /// Forward by the shown trump once it's revealed.  The card
+    /// becomes known to everyone.  Nothing happens if it turns out
+    /// not to be a valid trump, or the attacks have changed since.
+    pub fn confirm_shown_trump(&mut self, card: Card) -> HandleResult<()> {
+        let def = self.get_player_by_role(Role::Defender)?.addr();
+        if self.check_forward(&def, &card, false).is_err() {
+            return Ok(());
+        }
+        self.shown_trump_idxs.push(card.idx);
+        let p = self.get_player_by_role_mut(Role::Defender)?;
+        if !p.known_cards.iter().any(|c| c.idx == card.idx) {
+            p.known_cards.push(card.clone());
+        }
+        self.displays.push(Display::TrumpShown {
+            addr: def,
+            card: card.clone(),
+        });
+        self.forward(&card, false)
+    }
+
+    /// Forward by the card once it's revealed.  Nothing happens if
+    /// the attacks have changed since.
+    pub fn confirm_forward(&mut self, card: Card) -> HandleResult<()> {
+        let def = self.get_player_by_role(Role::Defender)?.addr();
+        if self.check_forward(&def, &card, true).is_err() {
+            return Ok(());
+        }
+        self.forward(&card, true)
+    }
+
     pub fn has_role_player(&self, role: Role) -> bool {
         self.players
             .values()
-            .find(|p| p.role.as_ref().is_some_and(|p| p.eq(&role)))
+            .find(|p| p.role == Some(role))
             .is_some()
     }
 
@@ -411,7 +1180,7 @@ impl Durak {
         Ok(self
             .players
             .values()
-            .find(|p| p.role.as_ref().is_some_and(|p| p.eq(&role)))
+            .find(|p| p.role == Some(role))
             .ok_or(Error::NoPlayerFoundByRole(role))?)
     }
 
@@ -420,7 +1189,7 @@ impl Durak {
         Ok(self
             .players
             .values_mut()
-            .find(|p| p.role.as_ref().is_some_and(|p| p.eq(&role)))
+            .find(|p| p.role == Some(role))
             .ok_or(Error::NoPlayerFoundByRole(role))?)
     }
 
@@ -443,7 +1212,11 @@ impl Durak {
         let Some(trump) = revealed.get(&TRUMP_IDX) else {
             Err(Error::TrumpNotRevealed)?
         };
-        self.trump = Some(Card::new(TRUMP_IDX, trump.to_owned()));
+        let trump = Card::new(TRUMP_IDX, trump.to_owned());
+        self.displays.push(Display::TrumpRevealed {
+            card: trump.clone(),
+        });
+        self.trump = Some(trump);
         Ok(())
     }
 
@@ -458,7 +1231,7 @@ impl Durak {
     /// and defends.
     pub fn set_timeout_or_end_round(&mut self, effect: &mut Effect) -> HandleResult<()> {
         // Do nothing if the end is already ended
-        if self.stage == Stage::EndOfGame {
+        if self.is_game_over() {
             return Ok(());
         }
         if self.is_fully_defended()? {
@@ -481,35 +1254,348 @@ impl Durak {
 
     pub fn remove_roles_for_escaped_players(&mut self) {
         for p in self.players.values_mut() {
-            if p.rank().is_some() {
+            if p.rank().is_some() && p.role.is_some() {
                 p.set_role(None);
+                self.displays.push(Display::RoleChanged {
+                    addr: p.addr(),
+                    role: None,
+                });
             }
         }
     }
 
-    /// Settle the game result
-    ///
-    /// We use a simple rule to transfer tokens:
-    ///     The first finished player got all tokens from the last player
-    pub fn settle_game(&mut self, effect: &mut Effect) -> HandleResult<()> {
-        let players = self.players_in_rank_order();
-        let winner = players.first().ok_or(Error::EmptyPlayers)?;
-        let loser = players.last().ok_or(Error::EmptyPlayers)?;
-        effect.settle(Settle::add(&winner.addr, self.bet_amount));
-        effect.settle(Settle::sub(&loser.addr, self.bet_amount));
-        for p in players {
-            effect.settle(Settle::eject(&p.addr));
-        }
-        effect.checkpoint();
-        effect.wait_timeout(RESET_TIMEOUT_MS);
+    /// Whether a series has started and is not over yet.
+    pub fn is_series_in_progress(&self) -> bool {
+        matches!(self.mode, GameMode::Series { .. }) && self.num_of_played_games > 0
+    }
+
+    /// Whether a tournament has started and is not completed yet.
+    pub fn is_tournament_in_progress(&self) -> bool {
+        self.mode == GameMode::Tournament && self.num_of_played_games > 0
+    }
+
+    /// Whether the series is over, by the number of games or points.
+    pub fn is_series_over(&self) -> bool {
+        match self.mode {
+            GameMode::Single | GameMode::Tournament => true,
+            GameMode::Series {
+                num_of_games,
+                target_points,
+            } => {
+                (num_of_games > 0 && self.num_of_played_games >= num_of_games)
+                    || (target_points > 0
+                        && self.penalty_points.values().any(|p| *p >= target_points))
+            }
+        }
+    }
+
+    /// Return the player addresses ordered by the series result.
+    /// Players with fewer penalty points stand higher, the ties are
+    /// broken by the ranks of the last game.
+    pub fn series_standings(&self) -> Vec<String> {
+        let mut players: Vec<(u16, String)> = self
+            .players_in_rank_order()
+            .into_iter()
+            .map(|p| {
+                let points = self.penalty_points.get(&p.addr).copied().unwrap_or(0);
+                (points, p.addr())
+            })
+            .collect();
+        // Stable sort keeps the rank order for ties
+        players.sort_by_key(|(points, _)| *points);
+        players.into_iter().map(|(_, addr)| addr).collect()
+    }
+
+    /// Whether the game is over, the hands may be still revealing.
+    pub fn is_game_over(&self) -> bool {
+        matches!(self.stage, Stage::RevealingHands | Stage::EndOfGame)
+    }
+
+    /// Return the multiplier of the loss by the shame rule, based on
+    /// the revealed hand of the durak.
+    pub fn loss_multiplier(&self) -> u64 {
+        let Some(rule) = self.shame_rule.as_ref() else {
+            return 1;
+        };
+        self.players_in_rank_order()
+            .last()
+            .map(|p| rule.multiplier(p.known_cards()))
+            .unwrap_or(1)
+    }
+
+    /// Settle the game result
+    ///
+    /// For a single game, the result is settled immediately, the
+    /// loss is multiplied by the shame rule within the balance of the
+    /// durak.  For a series, the durak gets penalty points, and the
+    /// result is settled by the standings when the series is over.
+    pub fn settle_game(&mut self, effect: &mut Effect) -> HandleResult<()> {
+        let multiplier = self.loss_multiplier();
+        match self.mode {
+            GameMode::Single => {
+                let players = self.players_in_rank_order();
+                let durak = players.last().ok_or(Error::EmptyPlayers)?;
+                let loss = self.stake.saturating_mul(multiplier).min(durak.balance);
+                let standings = players.iter().map(|p| p.addr()).collect();
+                self.settle_standings(standings, loss, effect)
+            }
+            GameMode::Series { .. } => {
+                let durak = self
+                    .players_in_rank_order()
+                    .last()
+                    .ok_or(Error::EmptyPlayers)?
+                    .addr();
+                *self.penalty_points.entry(durak).or_insert(0) += multiplier as u16;
+                self.num_of_played_games += 1;
+                if self.is_series_over() {
+                    let standings = self.series_standings();
+                    self.num_of_played_games = 0;
+                    self.penalty_points.clear();
+                    self.entrant_addrs.clear();
+                    self.settle_standings(standings, self.stake, effect)
+                } else {
+                    self.wait_for_next_game(effect);
+                    Ok(())
+                }
+            }
+            GameMode::Tournament => {
+                let durak = self
+                    .players_in_rank_order()
+                    .last()
+                    .ok_or(Error::EmptyPlayers)?
+                    .addr();
+                self.eliminated_addrs.push(durak);
+                self.num_of_played_games += 1;
+                // The tournament is over when only the winner is left
+                let num_of_survivors = self
+                    .num_of_players
+                    .checked_sub(1)
+                    .ok_or(Error::InvalidNumOfPlayers)?;
+                if num_of_survivors <= 1 {
+                    let standings = self.tournament_standings();
+                    self.settle_tournament(standings, effect)
+                } else {
+                    self.wait_for_next_game(effect);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Save the progress, and start the next game of the series or
+    /// the tournament after a while.
+    fn wait_for_next_game(&mut self, effect: &mut Effect) {
+        self.displays.push(Display::GameOver { payouts: vec![] });
+        effect.checkpoint();
+        effect.wait_timeout(RESET_TIMEOUT_MS);
+        self.timeout = effect.timestamp() + RESET_TIMEOUT_MS;
+    }
+
+    /// Return the player addresses ordered by the tournament result,
+    /// the winner first and the first eliminated player last.
+    pub fn tournament_standings(&self) -> Vec<String> {
+        let mut standings: Vec<String> = self
+            .players
+            .keys()
+            .filter(|addr| !self.eliminated_addrs.contains(addr))
+            .cloned()
+            .collect();
+        standings.extend(self.eliminated_addrs.iter().rev().cloned());
+        standings
+    }
+
+    /// Settle the tournament by the `standings`.
+    ///
+    /// Everyone pays the buy-in to the prize pool.  The rake is taken
+    /// from the pool, and the rest is paid out by the payout rule.
+    /// The players eliminated in the previous games are ejected.
+    pub fn settle_tournament(
+        &mut self,
+        standings: Vec<String>,
+        effect: &mut Effect,
+    ) -> HandleResult<()> {
+        if standings.len() < 2 {
+            Err(Error::InvalidNumOfPlayers)?
+        }
+        let buy_in = self.stake;
+        let mut pool = buy_in.saturating_mul(standings.len() as u64);
+        if let Some(rake) = self.rake {
+            let fee = rake.fee(pool);
+            if fee > 0 {
+                effect.transfer(rake.slot_id, fee);
+                pool -= fee;
+            }
+        }
+        let prizes = self.payout_rule.shares(pool, standings.len());
+        let mut payouts = vec![];
+        for (addr, prize) in standings.iter().zip(prizes) {
+            let p = self.players.get_mut(addr);
+            let change = if prize >= buy_in {
+                let gain = prize - buy_in;
+                if gain > 0 {
+                    effect.settle(Settle::add(addr, gain));
+                }
+                if let Some(p) = p {
+                    p.balance += gain;
+                }
+                gain as i64
+            } else {
+                let loss = buy_in - prize;
+                effect.settle(Settle::sub(addr, loss));
+                if let Some(p) = p {
+                    p.balance = p.balance.saturating_sub(loss);
+                }
+                -(loss as i64)
+            };
+            payouts.push(Payout {
+                addr: addr.to_owned(),
+                change,
+            });
+        }
+        for addr in self.eliminated_addrs.iter() {
+            if !self.players.contains_key(addr) {
+                effect.settle(Settle::eject(addr));
+            }
+        }
+        self.eliminated_addrs.clear();
+        self.entrant_addrs.clear();
+        self.num_of_played_games = 0;
+        self.finish_settlement(payouts, effect);
+        Ok(())
+    }
+
+    /// Transfer tokens by the `standings`, then start the rematch phase.
+    ///
+    /// The last player, the durak, pays the `loss`.  The rake is
+    /// taken from it, and the rest is shared by the others according
+    /// to the payout rule.
+    pub fn settle_standings(
+        &mut self,
+        standings: Vec<String>,
+        loss: u64,
+        effect: &mut Effect,
+    ) -> HandleResult<()> {
+        let (durak, receivers) = standings.split_last().ok_or(Error::EmptyPlayers)?;
+        if receivers.is_empty() {
+            Err(Error::InvalidNumOfPlayers)?
+        }
+        let mut winnings = loss;
+        if let Some(rake) = self.rake {
+            let fee = rake.fee(winnings);
+            if fee > 0 {
+                effect.transfer(rake.slot_id, fee);
+                winnings -= fee;
+            }
+        }
+        let shares = self.payout_rule.shares(winnings, receivers.len());
+        let mut payouts = vec![];
+        for (addr, share) in receivers.iter().zip(shares) {
+            if share == 0 {
+                continue;
+            }
+            effect.settle(Settle::add(addr, share));
+            if let Some(p) = self.players.get_mut(addr) {
+                p.balance += share;
+            }
+            payouts.push(Payout {
+                addr: addr.to_owned(),
+                change: share as i64,
+            });
+        }
+        effect.settle(Settle::sub(durak, loss));
+        if let Some(p) = self.players.get_mut(durak) {
+            p.balance = p.balance.saturating_sub(loss);
+        }
+        payouts.push(Payout {
+            addr: durak.to_owned(),
+            change: -(loss as i64),
+        });
+        self.finish_settlement(payouts, effect);
         Ok(())
     }
 
+    /// Show the payouts, then start the rematch phase.
+    fn finish_settlement(&mut self, payouts: Vec<Payout>, effect: &mut Effect) {
+        self.displays.push(Display::GameOver { payouts });
+        effect.checkpoint();
+        // Players are kept seated until the rematch phase ends
+        effect.allow_exit(true);
+        effect.wait_timeout(RESET_TIMEOUT_MS);
+        self.timeout = effect.timestamp() + RESET_TIMEOUT_MS;
+    }
+
+    /// The number of players who haven't finished.
+    pub fn num_of_remaining_players(&self) -> HandleResult<usize> {
+        Ok(self
+            .num_of_players
+            .checked_sub(self.num_of_finished)
+            .ok_or(Error::TooManyFinishedPlayers(
+                self.num_of_finished,
+                self.num_of_players,
+            ))?)
+    }
+
     /// End the game if there's only one player left
     pub fn maybe_end_game(&mut self, effect: &mut Effect) -> HandleResult<()> {
-        if self.num_of_finished >= self.num_of_players - 1 {
-            self.stage = Stage::EndOfGame;
-            return self.settle_game(effect);
+        if self.num_of_players == 0 {
+            Err(Error::InvalidNumOfPlayers)?
+        }
+        if self.num_of_remaining_players()? <= 1 {
+            return self.reveal_hands_or_settle(effect);
+        }
+        Ok(())
+    }
+
+    /// Return the card indexes remaining in hands, they are revealed
+    /// at the end of game to make the result auditable.
+    pub fn hand_idxs_to_reveal(&self) -> Vec<usize> {
+        self.players
+            .values()
+            .flat_map(|p| p.card_idxs.iter().copied())
+            .collect()
+    }
+
+    /// Reveal the hands at the end of game, settle the game once all
+    /// of them are revealed.
+    pub fn reveal_hands_or_settle(&mut self, effect: &mut Effect) -> HandleResult<()> {
+        let mut idxs = self.hand_idxs_to_reveal();
+        if !idxs.is_empty() {
+            if let Ok(revealed) = effect.get_revealed(self.random_id) {
+                idxs.retain(|i| !revealed.contains_key(i));
+            }
+            if !idxs.is_empty() {
+                self.stage = Stage::RevealingHands;
+                effect.reveal(self.random_id, idxs);
+                return Ok(());
+            }
+            self.update_revealed_hands(effect)?;
+        }
+        self.stage = Stage::EndOfGame;
+        self.settle_game(effect)
+    }
+
+    /// Add the revealed cards in hands to the public knowledge.
+    pub fn update_revealed_hands(&mut self, effect: &Effect) -> HandleResult<()> {
+        let revealed = effect.get_revealed(self.random_id)?;
+        for p in self.players.values_mut() {
+            if p.card_idxs.is_empty() {
+                continue;
+            }
+            let mut cards = Vec::with_capacity(p.card_idxs.len());
+            for idx in p.card_idxs.iter() {
+                let value = revealed
+                    .get(idx)
+                    .ok_or(Error::UnexpectedUnrevealedCard(*idx as u8))?;
+                let card = Card::new(*idx, value.to_owned());
+                if !p.known_cards.iter().any(|c| c.idx == *idx) {
+                    p.known_cards.push(card.clone());
+                }
+                cards.push(card);
+            }
+            self.displays.push(Display::HandRevealed {
+                addr: p.addr(),
+                cards,
+            });
         }
         Ok(())
     }
@@ -517,7 +1603,7 @@ impl Durak {
     /// End the round
     pub fn end_round(&mut self, attack_succeed: bool, effect: &mut Effect) -> HandleResult<()> {
         // Do nothing if the game is already ended
-        if self.stage == Stage::EndOfGame {
+        if self.is_game_over() {
             return Ok(());
         }
 
@@ -530,6 +1616,15 @@ impl Durak {
             return Ok(());
         }
 
+        self.displays.push(Display::RoundEnded { attack_succeed });
+        // Draw by the roles of this round, before they are rotated
+        let drawing_order = self.drawing_order()?;
+        self.showing_trump_idx = None;
+        self.shown_trump_idxs.clear();
+        self.passed_addrs.clear();
+        self.forwarding_card = None;
+        self.declared_cards.clear();
+
         // If the attack was succeed, the defender takes all cards,
         // otherwise we drop all cards
         if attack_succeed {
@@ -547,34 +1642,43 @@ impl Durak {
             self.attacks.clear();
         }
 
-        if self.deck_offset < DECK_LEN - 1
+        if !self.is_deck_empty()
             && self
                 .players
                 .values()
                 .find(|p| p.card_idxs.len() < MIN_HAND_CARD_COUNT)
                 .is_some()
         {
-            self.deal_cards(effect)?;
-        } else {
-            self.ask_to_act(effect)?;
+            self.deal_cards(&drawing_order, effect)?;
         }
         self.rotate_roles(attack_succeed)?;
         self.remove_roles_for_escaped_players();
+        // Ask the new roles to act, otherwise an escaped defender
+        // would end the round again and again.
+        if self.stage != Stage::Dealing {
+            self.ask_to_act(effect)?;
+        }
         Ok(())
     }
 
     /// Update the escaped players and maybe end the game when there's
     /// only one player left.
     pub fn update_escaped_players(&mut self) -> HandleResult<()> {
-        if self.deck_offset == DECK_LEN - 1 {
+        if self.is_deck_empty() {
             let mut num_of_finished = self.num_of_finished;
             let players = self.players_in_acting_order_mut(Role::Attacker)?;
+            let mut displays = vec![];
             for p in players {
                 if p.card_idxs().is_empty() && p.rank().is_none() {
                     p.set_rank(num_of_finished as u8);
+                    displays.push(Display::PlayerEscaped {
+                        addr: p.addr(),
+                        rank: num_of_finished as u8,
+                    });
                     num_of_finished += 1;
                 }
             }
+            self.displays.append(&mut displays);
             self.num_of_finished = num_of_finished;
         }
         Ok(())
@@ -604,18 +1708,22 @@ impl Durak {
 
     pub fn update_attack_space(&mut self) -> HandleResult<()> {
         let def = self.get_player_by_role(Role::Defender)?;
-        self.attack_space = def.card_idxs().len()
-            - self
-                .attacks
-                .iter()
-                .filter(|a| a.is_open_or_confirm_open())
-                .count();
+        let open_count = self
+            .attacks
+            .iter()
+            .filter(|a| a.is_open_or_confirm_open())
+            .count();
+        let num_of_cards = def.card_idxs().len();
+        self.attack_space = num_of_cards
+            .checked_sub(open_count)
+            .ok_or(Error::TooManyOpenAttacks(num_of_cards, open_count))?;
         Ok(())
     }
 
     /// Ask the players to act
     pub fn ask_to_act(&mut self, effect: &mut Effect) -> HandleResult<()> {
         self.stage = Stage::Acting;
+        self.displays.push(Display::RoundStarted);
         self.set_timeout_or_end_round(effect)?;
         self.update_attack_space()?;
         Ok(())
@@ -624,6 +1732,22 @@ impl Durak {
     /// Update the attack states based on the decrypted information.
     pub fn update_attacks(&mut self, effect: &mut Effect) -> HandleResult<()> {
         let revealed = effect.get_revealed(self.random_id)?;
+        if let Some(idx) = self.showing_trump_idx {
+            if let Some(value) = revealed.get(&idx) {
+                self.showing_trump_idx = None;
+                self.confirm_shown_trump(Card::new(idx, value.to_owned()))?;
+            }
+        }
+        if let Some(card) = self.forwarding_card.clone() {
+            if let Some(value) = revealed.get(&card.idx) {
+                self.forwarding_card = None;
+                // Not forwarded if the card is not the declared one
+                if card.value.eq(value) {
+                    self.confirm_forward(card)?;
+                }
+            }
+        }
+        self.reject_declared_lies(revealed)?;
         for attack in self.attacks.iter_mut() {
             match attack {
                 Attack::ConfirmOpen { open_idx } => {
@@ -643,11 +1767,61 @@ impl Durak {
                 _ => (),
             }
         }
-        self.update_attack_space()?;
         self.update_escaped_players()?;
         self.maybe_end_game(effect)?;
+        if !self.is_game_over() {
+            self.update_attack_space()?;
+        }
         self.set_timeout_or_end_round(effect)?;
         self.beated_addrs.clear();
+        // A new card is thrown, the others may throw again
+        self.passed_addrs.clear();
+        Ok(())
+    }
+
+    /// Record the values declared by `addr` for the played `cards`,
+    /// to be checked once they are revealed.
+    pub fn declare_cards(&mut self, addr: &str, cards: &[Card]) {
+        self.declared_cards
+            .extend(cards.iter().map(|card| DeclaredCard {
+                addr: addr.to_owned(),
+                card: card.clone(),
+            }));
+    }
+
+    /// Check the declared cards which are revealed.  A card which
+    /// turns out to be different is given back to the player, the
+    /// thrown card is dropped from the attacks, the closed attack is
+    /// open again.
+    pub fn reject_declared_lies(&mut self, revealed: &HashMap<usize, String>) -> HandleResult<()> {
+        let (checked, pending): (Vec<DeclaredCard>, Vec<DeclaredCard>) =
+            take(&mut self.declared_cards)
+                .into_iter()
+                .partition(|d| revealed.contains_key(&d.card.idx));
+        self.declared_cards = pending;
+        for DeclaredCard { addr, card } in checked {
+            if card.value.eq(&revealed[&card.idx]) {
+                continue;
+            }
+            let thrown = self.attacks.iter().position(
+                |a| matches!(a, Attack::ConfirmOpen { open_idx } if *open_idx == card.idx),
+            );
+            let closing = self.attacks.iter_mut().find(
+                |a| matches!(a, Attack::ConfirmClose { close_idx, .. } if *close_idx == card.idx),
+            );
+            if let Some(i) = thrown {
+                self.attacks.remove(i);
+            } else if let Some(attack) = closing {
+                attack.reopen()?;
+            } else {
+                continue;
+            }
+            let p = self
+                .players
+                .get_mut(&addr)
+                .ok_or(Error::PlayerNotFound(addr.clone()))?;
+            p.card_idxs.push(card.idx);
+        }
         Ok(())
     }
 
@@ -657,44 +1831,113 @@ impl Durak {
         effect: &mut Effect,
     ) -> HandleResult<()> {
         let revealed = effect.get_revealed(self.random_id)?;
-        idxs.retain(|i| !revealed.contains_key(&i));
-        if idxs.is_empty() {
-            self.update_attacks(effect)?;
-        } else {
+        idxs.retain(|i| !revealed.contains_key(i));
+        if !idxs.is_empty() {
             effect.reveal(self.random_id, idxs);
+            return Ok(());
+        }
+        // Otherwise the attacks are updated once the cards played
+        // before are revealed
+        let is_all_revealed = self
+            .attacks
+            .iter()
+            .filter(|a| !a.is_confirmed())
+            .flat_map(Attack::card_idxs)
+            .all(|i| revealed.contains_key(&i));
+        if is_all_revealed {
+            self.update_attacks(effect)?;
         }
         Ok(())
     }
 
+    /// Whether all cards, including the trump, have been dealt.
+    pub fn is_deck_empty(&self) -> bool {
+        self.deck_offset == DECK_LEN
+    }
+
+    /// Return the order to draw cards, the attacker draws first, then
+    /// the other players in acting order, the defender draws last.
+    pub fn drawing_order(&self) -> HandleResult<Vec<String>> {
+        let def = self.get_player_by_role(Role::Defender)?.addr();
+        let pos = self.get_player_by_role(Role::Attacker)?.position;
+        let mut players: Vec<&Player> = self
+            .players
+            .values()
+            .filter(|p| p.rank().is_none() && p.addr != def)
+            .collect();
+        players.sort_by_key(|p| {
+            if p.position >= pos {
+                p.position
+            } else {
+                p.position + MAX_NUM_OF_PLAYERS as u16
+            }
+        });
+        let mut order: Vec<String> = players.into_iter().map(Player::addr).collect();
+        order.push(def);
+        Ok(order)
+    }
+
     /// Dealing cards by assign cards to players.
     /// Each player will receive cards until he has 6 in hand.
-    /// This progress starts from the current attacker postion.
-    pub fn deal_cards(&mut self, effect: &mut Effect) -> HandleResult<()> {
+    /// This progress follows the `order`, see `drawing_order`.
+    ///
+    /// The trump is the last card of the deck.  It's already revealed
+    /// so it's dealt without assigning, and the player who receives
+    /// it is recorded as the trump holder.  The stage turns to
+    /// `Dealing` only when there are secrets to wait for.
+    pub fn deal_cards(&mut self, order: &[String], effect: &mut Effect) -> HandleResult<()> {
         let mut deck_offset = self.deck_offset;
         let random_id = self.random_id;
-        let players = self.players_in_acting_order_mut(Role::Attacker)?;
+        let trump = self.get_trump()?.clone();
+        let mut trump_holder = None;
+        let mut has_assigned = false;
         let mut displays = vec![];
-        for p in players.into_iter() {
+        for addr in order.iter() {
+            let Some(p) = self.players.get_mut(addr) else {
+                continue;
+            };
             let l = p.card_idxs.len();
             if l < MIN_HAND_CARD_COUNT {
                 let cnt = MIN_HAND_CARD_COUNT - l;
-                let new_offset = (deck_offset + cnt).min(DECK_LEN - 1);
-                let mut assign_idxs: Vec<usize> = (deck_offset..new_offset).collect();
-                effect.assign(random_id, &p.addr, assign_idxs.clone());
-                displays.push(Display::DealCadrs {
+                let new_offset = (deck_offset + cnt).min(DECK_LEN);
+                let mut deal_idxs: Vec<usize> = (deck_offset..new_offset).collect();
+                let assign_idxs: Vec<usize> = deal_idxs
+                    .iter()
+                    .copied()
+                    .filter(|i| *i != TRUMP_IDX)
+                    .collect();
+                if !assign_idxs.is_empty() {
+                    effect.assign(random_id, &p.addr, assign_idxs);
+                    has_assigned = true;
+                }
+                displays.push(Display::DealCards {
                     addr: p.addr.clone(),
-                    card_idxs: assign_idxs.clone(),
+                    card_idxs: deal_idxs.clone(),
                 });
-                p.card_idxs.append(&mut assign_idxs);
+                if deal_idxs.contains(&TRUMP_IDX) {
+                    p.known_cards.push(trump.clone());
+                    trump_holder = Some(p.addr.clone());
+                    displays.push(Display::TrumpDealt {
+                        addr: p.addr.clone(),
+                        card: trump.clone(),
+                    });
+                }
+                p.card_idxs.append(&mut deal_idxs);
                 deck_offset = new_offset;
-                if deck_offset == DECK_LEN - 1 {
+                if deck_offset == DECK_LEN {
                     break;
                 }
             }
         }
         self.displays.append(&mut displays);
+        if trump_holder.is_some() {
+            self.trump_holder = trump_holder;
+        }
         self.deck_offset = deck_offset;
-        self.stage = Stage::Dealing;
+        // Only the trump was dealt, no secrets to wait for
+        if has_assigned {
+            self.stage = Stage::Dealing;
+        }
         Ok(())
     }
 
@@ -707,29 +1950,9 @@ impl Durak {
         let act = action.clone();
         match action {
             Action::Attack { cards } => {
-                let l = cards.len();
-                if self.attack_space < l {
-                    Err(Error::NoAttackSpace(self.attack_space, l))?
-                }
-                if !self.can_attack()? {
-                    Err(Error::CantAttack)?
-                }
-                if !(self.attacks.is_empty() || cards.iter().any(|c| self.is_valid_attack_card(c)))
-                {
-                    Err(Error::NotValidAttackCard)?
-                }
-                let att = self.get_player_by_role_mut(Role::Attacker)?;
-                if att.addr.ne(&sender) {
-                    Err(Error::PlayerIsNotAttacker)?
-                }
-                let mut idxs = vec![];
-                let mut attacks = vec![];
-                for c in cards.iter() {
-                    let idx = att.take_card(c.idx)?;
-                    idxs.push(idx);
-                    attacks.push(Attack::new(idx));
-                }
-                self.attacks.append(&mut attacks);
+                self.check_attack(&sender, Role::Attacker, &cards)?;
+                let idxs = self.add_attacks(Role::Attacker, &cards)?;
+                self.declare_cards(&sender, &cards);
                 self.reveal_cards_or_update_attacks(idxs, effect)?;
                 self.displays.push(Display::PlayerAction {
                     addr: sender,
@@ -737,28 +1960,9 @@ impl Durak {
                 });
             }
             Action::CoAttack { cards } => {
-                let l = cards.len();
-                if self.attack_space < l {
-                    Err(Error::NoAttackSpace(self.attack_space, l))?
-                }
-                if !self.can_attack()? {
-                    Err(Error::CantAttack)?
-                }
-                if !cards.iter().any(|c| self.is_valid_attack_card(c)) {
-                    Err(Error::NotValidAttackCard)?
-                }
-                let coatt = self.get_player_by_role_mut(Role::CoAttacker)?;
-                if coatt.addr.ne(&sender) {
-                    Err(Error::PlayerIsNotCoAttacker)?
-                }
-                let mut idxs = vec![];
-                let mut attacks = vec![];
-                for c in cards.iter() {
-                    let idx = coatt.take_card(c.idx)?;
-                    idxs.push(idx);
-                    attacks.push(Attack::new(idx));
-                }
-                self.attacks.append(&mut attacks);
+                self.check_attack(&sender, Role::CoAttacker, &cards)?;
+                let idxs = self.add_attacks(Role::CoAttacker, &cards)?;
+                self.declare_cards(&sender, &cards);
                 self.reveal_cards_or_update_attacks(idxs, effect)?;
                 self.displays.push(Display::PlayerAction {
                     addr: sender,
@@ -766,60 +1970,56 @@ impl Durak {
                 });
             }
             Action::Defend { card, target } => {
-                if !self.can_defend()? {
-                    Err(Error::CantDefend)?
-                }
+                self.check_defend(&sender, &card, target)?;
                 let def = self.get_player_by_role_mut(Role::Defender)?;
                 def.take_card(card.idx)?;
-                if def.addr.ne(&sender) {
-                    Err(Error::PlayerIsNotDefender)?
-                }
-                let a = self.get_attack(target)?;
-                if !a.can_be_closed_by(&card, self.get_trump()?)? {
-                    Err(Error::InvalidDefendCard)?
-                }
-                let a = self.get_attack_mut(target)?;
-                a.close(&card)?;
+                self.get_attack_mut(target)?.close(&card)?;
+                self.declare_cards(&sender, std::slice::from_ref(&card));
                 self.reveal_cards_or_update_attacks(vec![card.idx], effect)?;
                 self.displays.push(Display::PlayerAction {
                     addr: sender,
                     action: act,
                 });
             }
-            Action::Forward { card } => {
-                // Conditions for forward
-                // All attacks are open, and having same kind
-                let mut kinds = vec![];
-                for att in self.attacks.iter() {
-                    match att {
-                        Attack::Open { open } => kinds.push(open.kind()),
-                        _ => return Err(Error::CantForward)?,
-                    }
+            Action::DefendMany { defenses } => {
+                self.check_defenses(&sender, &defenses)?;
+                let mut idxs = vec![];
+                for d in defenses.iter() {
+                    let def = self.get_player_by_role_mut(Role::Defender)?;
+                    idxs.push(def.take_card(d.card.idx)?);
+                    self.get_attack_mut(d.target)?.close(&d.card)?;
+                    self.declare_cards(&sender, std::slice::from_ref(&d.card));
                 }
-                if !kinds.windows(2).all(|w| w[0] == w[1]) {
-                    Err(Error::CantForward)?
+                self.reveal_cards_or_update_attacks(idxs, effect)?;
+                self.displays.push(Display::PlayerAction {
+                    addr: sender,
+                    action: act,
+                });
+            }
+            Action::Forward { card } => {
+                if self.showing_trump_idx.is_some() || self.forwarding_card.is_some() {
+                    Err(Error::UnconfirmedCard)?
                 }
-                if !self.is_valid_attack_card(&card) {
-                    Err(Error::InvalidForwardCard)?
+                self.check_forward(&sender, &card, true)?;
+                // Forward once the card is revealed to be what it's
+                // declared to be
+                let idx = card.idx;
+                self.forwarding_card = Some(card);
+                self.reveal_cards_or_update_attacks(vec![idx], effect)?;
+                self.displays.push(Display::PlayerAction {
+                    addr: sender,
+                    action: act,
+                });
+            }
+            Action::ShowTrumpForward { card } => {
+                if self.showing_trump_idx.is_some() || self.forwarding_card.is_some() {
+                    Err(Error::UnconfirmedCard)?
                 }
-                let def = self.get_player_by_role_mut(Role::Defender)?;
-                let idx = def.take_card(card.idx)?;
-                self.attacks.push(Attack::new(idx));
-
-                // The defender may escape due to forward
-                self.update_escaped_players()?;
-                // Forward roles, the current defender becomes attacker
-                // and others take the roles by their accordingly
-                self.rotate_roles(false)?;
-                let def = self.get_player_by_role(Role::Defender)?;
-
-                // Check if it's a valid forwarding
-                // The defender must have more cards than attacks'
-                if self.num_of_players - self.num_of_finished > 2
-                    && def.card_idxs.len() < self.attacks.iter().filter(|a| a.is_open_or_confirm_open()).count() {
-                    Err(Error::CantForward)?
+                if self.shown_trump_idxs.contains(&card.idx) {
+                    Err(Error::DuplicatedShownTrump)?
                 }
-
+                self.check_forward(&sender, &card, false)?;
+                self.showing_trump_idx = Some(card.idx);
                 self.reveal_cards_or_update_attacks(vec![card.idx], effect)?;
                 self.displays.push(Display::PlayerAction {
                     addr: sender,
@@ -827,7 +2027,10 @@ impl Durak {
                 });
             }
             Action::Take => {
-                let def = self.get_player_by_role_mut(Role::Defender)?;
+                if self.stage != Stage::Acting {
+                    Err(Error::InvalidStage(self.stage))?
+                }
+                let def = self.get_player_by_role(Role::Defender)?;
                 let def_addr = def.addr.clone();
                 if def_addr.ne(&sender) {
                     Err(Error::PlayerIsNotDefender)?
@@ -839,6 +2042,10 @@ impl Durak {
                     self.stage = Stage::EndOfRound;
                     effect.action_timeout(def_addr, END_OF_ROUND_TIMEOUT_MS);
                     self.timeout = effect.timestamp() + END_OF_ROUND_TIMEOUT_MS;
+                    // Nobody is able to throw in
+                    if self.is_all_throwers_passed()? {
+                        self.end_round(true, effect)?
+                    }
                 } else {
                     self.end_round(true, effect)?
                 }
@@ -848,6 +2055,9 @@ impl Durak {
                 });
             }
             Action::Beated => {
+                if self.stage != Stage::Acting {
+                    Err(Error::InvalidStage(self.stage))?
+                }
                 if self.beated_addrs.contains(&sender) {
                     Err(Error::DuplicatedBeated)?
                 }
@@ -876,25 +2086,1976 @@ impl Durak {
                     action: act,
                 });
             }
+            Action::Pass => {
+                if self.stage != Stage::EndOfRound {
+                    Err(Error::InvalidStage(self.stage))?
+                }
+                let p = self
+                    .players
+                    .get(&sender)
+                    .ok_or(Error::PlayerNotFound(sender.clone()))?;
+                if !p.can_attack() {
+                    Err(Error::PlayerIsNotAttacker)?
+                }
+                if self.passed_addrs.contains(&sender) {
+                    Err(Error::DuplicatedPass)?
+                }
+                if !self.is_all_attacks_confirmed() {
+                    Err(Error::UnconfirmedCard)?
+                }
+                self.passed_addrs.push(sender.clone());
+                if self.is_all_throwers_passed()? {
+                    self.end_round(true, effect)?;
+                }
+                self.displays.push(Display::PlayerAction {
+                    addr: sender,
+                    action: act,
+                });
+            }
+            Action::Rematch => {
+                if self.stage != Stage::EndOfGame {
+                    Err(Error::InvalidStage(self.stage))?
+                }
+                if self.is_series_in_progress() {
+                    Err(Error::SeriesInProgress)?
+                }
+                if self.is_tournament_in_progress() {
+                    Err(Error::TournamentInProgress)?
+                }
+                let p = self
+                    .players
+                    .get(&sender)
+                    .ok_or(Error::PlayerNotFound(sender.clone()))?;
+                // The sender must cover the stake of the rematch
+                // with everyone who has accepted it so far
+                let balances = self
+                    .rematch_addrs
+                    .iter()
+                    .filter_map(|addr| self.players.get(addr))
+                    .map(|p| p.balance)
+                    .chain(std::iter::once(p.balance));
+                match self.stake_for(balances) {
+                    Some(stake) if stake <= p.balance => {}
+                    _ => Err(Error::InsufficientBalance(p.balance))?,
+                }
+                if self.rematch_addrs.contains(&sender) {
+                    Err(Error::DuplicatedRematch)?
+                }
+                self.rematch_addrs.push(sender.clone());
+                self.displays.push(Display::PlayerAction {
+                    addr: sender,
+                    action: act,
+                });
+                if self.rematch_addrs.len() == self.players.len() {
+                    self.end_rematch(effect);
+                }
+            }
+            Action::Ready => {
+                if self.stage != Stage::Waiting {
+                    Err(Error::InvalidStage(self.stage))?
+                }
+                let p = self
+                    .players
+                    .get_mut(&sender)
+                    .ok_or(Error::PlayerNotFound(sender.clone()))?;
+                if p.ready {
+                    Err(Error::DuplicatedReady)?
+                }
+                p.ready = true;
+                p.idle_deadline = 0;
+                self.displays.push(Display::PlayerAction {
+                    addr: sender,
+                    action: act,
+                });
+                self.try_start_game(effect);
+            }
+            Action::Unready => {
+                if self.stage != Stage::Waiting {
+                    Err(Error::InvalidStage(self.stage))?
+                }
+                let p = self
+                    .players
+                    .get_mut(&sender)
+                    .ok_or(Error::PlayerNotFound(sender.clone()))?;
+                if !p.ready {
+                    Err(Error::NotReady)?
+                }
+                // The countdown starts over
+                p.ready = false;
+                p.idle_deadline = 0;
+                self.displays.push(Display::PlayerAction {
+                    addr: sender,
+                    action: act,
+                });
+                self.try_start_game(effect);
+            }
+            Action::RequestSeat { position } => {
+                if self.stage != Stage::Waiting {
+                    Err(Error::InvalidStage(self.stage))?
+                }
+                if position as usize >= self.num_of_seats {
+                    Err(Error::InvalidSeat(position))?
+                }
+                let from = self
+                    .players
+                    .get(&sender)
+                    .ok_or(Error::PlayerNotFound(sender.clone()))?
+                    .position;
+                if from == position {
+                    Err(Error::InvalidSeat(position))?
+                }
+                let owner = self.players.values_mut().find(|p| p.position == position);
+                let request = match owner {
+                    // Take the free seat
+                    None => None,
+                    // Swap the seats as both requested
+                    Some(owner) if owner.seat_request == Some(from) => {
+                        owner.position = from;
+                        owner.seat_request = None;
+                        None
+                    }
+                    // Wait for the owner to accept
+                    Some(_) => Some(position),
+                };
+                if let Some(p) = self.players.get_mut(&sender) {
+                    if request.is_none() {
+                        p.position = position;
+                    }
+                    p.seat_request = request;
+                }
+                self.displays.push(Display::PlayerAction {
+                    addr: sender,
+                    action: act,
+                });
+            }
         };
         Ok(())
     }
 }
 
+#[cfg(test)]
+pub(crate) mod fixture;
+
 #[cfg(test)]
 mod test {
+    use super::fixture::*;
     use super::*;
+    use crate::DurakAccount;
+    use race_api::types::SettleOp;
+    use std::collections::HashMap;
 
     #[test]
     fn test_is_covered_by() {
         let c1 = Card::new(0, "h2".into());
         let c2 = Card::new(1, "ha".into());
-        assert_eq!(c1.is_covered_by(&c2), true);
+        assert!(c1.is_covered_by(&c2));
         let c1 = Card::new(0, "d5".into());
         let c2 = Card::new(1, "dj".into());
-        assert_eq!(c1.is_covered_by(&c2), true);
+        assert!(c1.is_covered_by(&c2));
         let c1 = Card::new(0, "st".into());
         let c2 = Card::new(1, "sa".into());
-        assert_eq!(c1.is_covered_by(&c2), true);
+        assert!(c1.is_covered_by(&c2));
+    }
+
+    #[test]
+    fn test_malformed_card_values() {
+        for value in ["", "h", "\u{436}6", "6\u{436}"] {
+            let card = Card::new(0, value.into());
+            assert!(card.suit().len() <= 1);
+            assert!(card.kind().len() <= 1);
+            assert!(!Card::new(1, "h6".into()).is_covered_by(&card));
+        }
+
+        // A malformed card can't be used to defend
+        let (mut state, card) = forwarding_game();
+        let def = state.get_player_by_role(Role::Defender).unwrap().addr();
+        for value in ["", "s", "\u{436}"] {
+            let card = Card::new(card.idx, value.into());
+            let err = rejected(&mut state, &def, Action::Defend { card, target: 0 });
+            assert_eq!(err, Error::InvalidDefendCard.into());
+        }
+    }
+
+    #[test]
+    fn test_can_be_closed_by_all_combinations() {
+        let cards = all_cards();
+        for trump in cards.iter().filter(|c| c.kind() == "6") {
+            for open in cards.iter() {
+                for close in cards.iter().filter(|c| c.idx != open.idx) {
+                    let attack = Attack::Open { open: open.clone() };
+                    let closed = attack.can_be_closed_by(close, trump).unwrap();
+                    let expected = if open.is_same_suit(close) {
+                        kind_str_to_u8(close.kind()) > kind_str_to_u8(open.kind())
+                    } else {
+                        // Only trump can beat a card of the other suit
+                        close.is_same_suit(trump)
+                    };
+                    assert_eq!(
+                        closed, expected,
+                        "{} by {}, trump {}",
+                        open.value, close.value, trump.value
+                    );
+                    if close.is_same_suit(trump) && !open.is_same_suit(trump) {
+                        assert!(closed);
+                    }
+                    if open.is_same_suit(trump) && !close.is_same_suit(trump) {
+                        assert!(!closed);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_card_comparison_is_a_strict_order_in_suit() {
+        let cards = all_cards();
+        for a in cards.iter() {
+            assert!(!a.is_covered_by(a));
+            for b in cards.iter() {
+                if a.is_covered_by(b) {
+                    assert!(!b.is_covered_by(a));
+                    assert!(a.is_same_suit(b));
+                } else if a.is_same_suit(b) && a.idx != b.idx {
+                    assert!(b.is_covered_by(a));
+                }
+            }
+        }
+    }
+
+    /// Build an attack in each of the four states.
+    #[test]
+    fn test_illegal_attack_transitions() {
+        let cards = all_cards();
+        let (open, close, trump) = (&cards[0], &cards[1], &cards[8]);
+        for state in 0..4 {
+            let transitions: [(&str, bool); 5] = [
+                ("confirm_open", state == 0),
+                ("close", state == 1),
+                ("can_be_closed_by", state == 1),
+                ("confirm_close", state == 2),
+                ("into_cards", state == 1 || state == 3),
+            ];
+            for (name, legal) in transitions {
+                let mut attack = attack_in_state(state, open, close);
+                let before = attack.try_to_vec().unwrap();
+                let res = match name {
+                    "confirm_open" => attack.confirm_open(open.value.clone()),
+                    "close" => attack.close(close),
+                    "can_be_closed_by" => attack.can_be_closed_by(close, trump).map(|_| ()),
+                    "confirm_close" => attack.confirm_close(close.value.clone()),
+                    _ => attack_in_state(state, open, close).into_cards().map(|_| ()),
+                };
+                if legal {
+                    assert!(res.is_ok(), "{} in state {}", name, state);
+                } else {
+                    assert_eq!(
+                        res.unwrap_err(),
+                        Error::InvalidAttackStatus.into(),
+                        "{} in state {}",
+                        name,
+                        state
+                    );
+                    assert_eq!(attack.try_to_vec().unwrap(), before);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_illegal_attack_transitions_by_events() -> anyhow::Result<()> {
+        let mut state = acting_game();
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        let def = state.get_player_by_role(Role::Defender)?;
+        let (def, d) = (def.addr(), def.card_idxs.clone());
+        let a = state.players[&att].card_idxs[0];
+        let open = Card::new(a, "s6".into());
+        let (close, other) = (Card::new(d[0], "s7".into()), Card::new(d[1], "s8".into()));
+        let custom = |sender: &str, action: Action| Event::Custom {
+            sender: sender.into(),
+            raw: action.try_to_vec().unwrap(),
+        };
+        let defend = |card: &Card| Action::Defend {
+            card: card.clone(),
+            target: 0,
+        };
+        let secrets_ready = Event::SecretsReady {
+            random_ids: vec![state.random_id],
+        };
+        let handle = |state: &mut Durak, revealed_cards: &[&Card], event: Event| {
+            let mut effect = revealed(state, revealed_cards);
+            state.handle_event(&mut effect, event)
+        };
+
+        let attack = Action::Attack {
+            cards: vec![open.clone()],
+        };
+        handle(&mut state, &[], custom(&att, attack))?;
+        assert!(matches!(
+            state.attacks.as_slice(),
+            [Attack::ConfirmOpen { open_idx }] if *open_idx == a
+        ));
+
+        // The attack can't be defended or taken until it's revealed
+        let res = handle(&mut state, &[], custom(&def, defend(&close)));
+        assert_eq!(res.unwrap_err(), Error::InvalidAttackStatus.into());
+        let res = handle(&mut state, &[], custom(&def, Action::Take));
+        assert_eq!(res.unwrap_err(), Error::UnconfirmedCard.into());
+        assert!(matches!(
+            state.attacks.as_slice(),
+            [Attack::ConfirmOpen { .. }]
+        ));
+
+        handle(&mut state, &[&open], secrets_ready.clone())?;
+        assert!(matches!(
+            state.attacks.as_slice(),
+            [Attack::Open { open: o }] if o.value == "s6"
+        ));
+
+        // The attack can't be closed twice while the close is pending
+        handle(&mut state, &[], custom(&def, defend(&close)))?;
+        assert!(matches!(
+            state.attacks.as_slice(),
+            [Attack::ConfirmClose { open: o, close_idx }] if o.idx == a && *close_idx == d[0]
+        ));
+        let res = handle(&mut state, &[], custom(&def, defend(&other)));
+        assert_eq!(res.unwrap_err(), Error::InvalidAttackStatus.into());
+        assert!(matches!(
+            state.attacks.as_slice(),
+            [Attack::ConfirmClose { close_idx, .. }] if *close_idx == d[0]
+        ));
+
+        handle(&mut state, &[&close], secrets_ready)?;
+        assert!(matches!(
+            state.attacks.as_slice(),
+            [Attack::Closed { open: o, close: c }] if o.value == "s6" && c.value == "s7"
+        ));
+
+        // A closed attack stays closed
+        let res = handle(&mut state, &[], custom(&def, defend(&other)));
+        assert_eq!(res.unwrap_err(), Error::InvalidAttackStatus.into());
+        assert!(matches!(
+            state.attacks.as_slice(),
+            [Attack::Closed { close: c, .. }] if c.idx == d[0]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_attack_transitions_keep_cards() -> anyhow::Result<()> {
+        let cards = all_cards();
+        for open in cards.iter() {
+            for close in cards.iter().filter(|c| c.idx != open.idx) {
+                let mut attack = Attack::new(open.idx);
+                assert_eq!(attack.card_idxs(), vec![open.idx]);
+                attack.confirm_open(open.value.clone())?;
+                assert!(attack.is_open());
+                attack.close(close)?;
+                assert!(!attack.is_confirmed());
+                assert_eq!(attack.card_idxs(), vec![open.idx, close.idx]);
+                attack.confirm_close(close.value.clone())?;
+                assert!(attack.is_closed());
+                let values: Vec<String> =
+                    attack.into_cards()?.into_iter().map(|c| c.value).collect();
+                assert_eq!(values, vec![open.value.clone(), close.value.clone()]);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rematch_ejects_declined_players() -> anyhow::Result<()> {
+        let mut effect = Effect::default();
+        let mut state = ended_game(&["alice", "bob", "carol"], &mut effect);
+        assert_eq!(state.stage, Stage::EndOfGame);
+        assert_eq!(state.players["alice"].balance(), 200);
+        assert_eq!(state.players["carol"].balance(), 0);
+
+        let mut effect = Effect::default();
+        state.handle_action(&mut effect, "alice".into(), Action::Rematch)?;
+        let res = state.handle_action(&mut effect, "alice".into(), Action::Rematch);
+        assert_eq!(res.unwrap_err(), Error::DuplicatedRematch.into());
+        let res = state.handle_action(&mut effect, "carol".into(), Action::Rematch);
+        assert_eq!(res.unwrap_err(), Error::InsufficientBalance(0).into());
+        state.handle_action(&mut effect, "bob".into(), Action::Rematch)?;
+
+        let mut effect = Effect::default();
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert_eq!(state.stage, Stage::Waiting);
+        assert_eq!(effect.settles, vec![Settle::eject("carol")]);
+        assert!(!effect.start_game);
+        assert!(state.players.contains_key("alice"));
+        assert!(state.players.contains_key("bob"));
+        assert!(state.players["alice"].rank().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rematch_starts_next_game() -> anyhow::Result<()> {
+        let mut effect = Effect::default();
+        let mut state = ended_game(&["alice", "bob"], &mut effect);
+        state.players.get_mut("bob").unwrap().balance = 100;
+
+        let mut effect = Effect::default();
+        state.handle_action(&mut effect, "alice".into(), Action::Rematch)?;
+        assert!(!effect.start_game);
+        state.handle_action(&mut effect, "bob".into(), Action::Rematch)?;
+        assert!(effect.start_game);
+        assert!(effect.settles.is_empty());
+        assert_eq!(state.stage, Stage::Shuffling);
+        assert_eq!(state.players.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rematch_checks_the_stake_to_be_charged() -> anyhow::Result<()> {
+        let mut effect = Effect::default();
+        let mut state = ended_game(&["alice", "bob", "carol"], &mut effect);
+        state.min_num_of_players = 2;
+        state.stake_rule = StakeRule::MinBalance;
+        state.players.get_mut("alice").unwrap().balance = 300;
+        state.players.get_mut("bob").unwrap().balance = 150;
+        state.players.get_mut("carol").unwrap().balance = 80;
+
+        let mut effect = Effect::default();
+        state.handle_action(&mut effect, "alice".into(), Action::Rematch)?;
+        let res = state.handle_action(&mut effect, "carol".into(), Action::Rematch);
+        assert_eq!(res.unwrap_err(), Error::InsufficientBalance(80).into());
+        state.handle_action(&mut effect, "bob".into(), Action::Rematch)?;
+
+        // Carol's seat is left for a newcomer until the countdown ends
+        let mut effect = Effect::default();
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert!(!effect.start_game);
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS));
+        let mut effect = effect_at(START_COUNTDOWN_MS);
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert!(effect.start_game);
+        assert_eq!(state.stake, 150);
+        Ok(())
+    }
+
+    #[test]
+    fn test_series_settles_at_the_end() -> anyhow::Result<()> {
+        let mut state = game_with_players(&["alice", "bob"]);
+        state.mode = GameMode::Series {
+            num_of_games: 3,
+            target_points: 2,
+        };
+
+        let mut effect = Effect::default();
+        finish_game(&mut state, &["alice", "bob"], &mut effect);
+        assert!(effect.settles.is_empty());
+        assert!(effect.is_checkpoint);
+        assert!(state.is_series_in_progress());
+        assert_eq!(state.penalty_points["bob"], 1);
+        let res = state.handle_action(&mut effect, "alice".into(), Action::Rematch);
+        assert_eq!(res.unwrap_err(), Error::SeriesInProgress.into());
+
+        let mut effect = Effect::default();
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert!(effect.start_game);
+        assert!(!effect.allow_exit);
+        assert_eq!(state.stage, Stage::Shuffling);
+
+        let mut effect = Effect::default();
+        finish_game(&mut state, &["bob", "alice"], &mut effect);
+        assert!(effect.settles.is_empty());
+        assert_eq!(state.num_of_played_games, 2);
+
+        // Bob reaches the target points
+        let mut effect = Effect::default();
+        finish_game(&mut state, &["alice", "bob"], &mut effect);
+        assert_eq!(
+            effect.settles,
+            vec![Settle::add("alice", 100), Settle::sub("bob", 100)]
+        );
+        assert!(effect.allow_exit);
+        assert!(!state.is_series_in_progress());
+        assert!(state.penalty_points.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_series_queues_newcomers() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob"], 3, 2);
+        state.mode = GameMode::Series {
+            num_of_games: 2,
+            target_points: 0,
+        };
+        let mut effect = Effect::default();
+        finish_game(&mut state, &["alice", "bob"], &mut effect);
+
+        // Dave doesn't join the series with a free seat
+        let mut effect = Effect::default();
+        state.handle_event(&mut effect, sync("dave", 2, 100))?;
+        assert!(state.waiting_players.contains_key("dave"));
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert!(effect.start_game);
+        assert_eq!(state.num_of_players, 2);
+        assert!(!state.players.contains_key("dave"));
+
+        // Seated once the series is over
+        let mut effect = Effect::default();
+        finish_game(&mut state, &["bob", "alice"], &mut effect);
+        assert!(!state.is_series_in_progress());
+        state.reset(&mut effect);
+        state.try_start_game(&mut effect);
+        assert!(state.players.contains_key("dave"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tournament_pays_prizes_on_completion() -> anyhow::Result<()> {
+        let addrs = ["alice", "bob", "carol"];
+        let mut state = game_with_players(&addrs);
+        state.mode = GameMode::Tournament;
+        state.payout_rule = PayoutRule::RankWeighted(vec![7, 3]);
+
+        let mut effect = Effect::default();
+        finish_game(&mut state, &addrs, &mut effect);
+        assert!(effect.settles.is_empty());
+        assert!(effect.is_checkpoint);
+        assert!(state.is_tournament_in_progress());
+        assert_eq!(state.eliminated_addrs, vec!["carol".to_string()]);
+        let res = state.handle_action(&mut effect, "alice".into(), Action::Rematch);
+        assert_eq!(res.unwrap_err(), Error::TournamentInProgress.into());
+
+        // Carol is eliminated from the next game
+        let mut effect = Effect::default();
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert!(effect.start_game);
+        assert_eq!(state.num_of_players, 2);
+        assert!(!state.players.contains_key("carol"));
+
+        let mut effect = Effect::default();
+        finish_game(&mut state, &["bob", "alice"], &mut effect);
+        assert_eq!(
+            effect.settles,
+            vec![
+                Settle::add("bob", 110),
+                Settle::sub("alice", 10),
+                Settle::sub("carol", 100),
+                Settle::eject("carol"),
+            ]
+        );
+        assert!(!state.is_tournament_in_progress());
+        assert!(state.eliminated_addrs.is_empty());
+        assert_eq!(state.players["bob"].balance(), 210);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tournament_keeps_the_survivors_seated() -> anyhow::Result<()> {
+        let addrs = ["alice", "bob", "carol"];
+        let mut state = game_with_players(&addrs);
+        state.mode = GameMode::Tournament;
+        let mut effect = Effect::default();
+        finish_game(&mut state, &addrs, &mut effect);
+
+        // Nobody can leave between the games of the tournament
+        let mut effect = Effect::default();
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert!(effect.start_game);
+        assert!(!effect.allow_exit);
+        assert_eq!(state.entrant_addrs, addrs);
+
+        // A late joiner waits until the tournament is over
+        state.stage = Stage::Waiting;
+        state.add_player(Player::new("dave".into(), 2, 100));
+        assert!(state.waiting_players.contains_key("dave"));
+
+        // The next game waits for every survivor
+        let bob = state.players.remove("bob").unwrap();
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert!(!effect.start_game);
+        state.players.insert(bob.addr(), bob);
+        state.try_start_game(&mut effect);
+        assert!(effect.start_game);
+        assert_eq!(state.num_of_players, 2);
+        assert_eq!(state.entrant_addrs, addrs);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_tournament_from_checkpoint() -> anyhow::Result<()> {
+        let addrs = ["alice", "bob", "carol"];
+        let mut state = game_with_players(&addrs);
+        state.mode = GameMode::Tournament;
+        state.stake_rule = StakeRule::MinBalance;
+        for p in state.players.values_mut() {
+            p.balance = 150;
+        }
+        set_ready(&mut state, &addrs);
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert_eq!(state.stake, 150);
+        finish_game(&mut state, &addrs, &mut effect);
+        assert!(effect.is_checkpoint);
+
+        // The game is restored from the checkpoint
+        let account = DurakAccount {
+            bet_amount: 100,
+            num_of_players: 3,
+            mode: GameMode::Tournament,
+            payout_rule: PayoutRule::WinnerTakesAll,
+            rake: None,
+            shame_rule: None,
+            stake_rule: StakeRule::MinBalance,
+            min_num_of_players: 0,
+        };
+        let init_account = InitAccount {
+            data: account.try_to_vec()?,
+            checkpoint: state.into_checkpoint()?.try_to_vec()?,
+            ..Default::default()
+        };
+        let mut effect = Effect::default();
+        let mut state = Durak::init_state(&mut effect, init_account)?;
+        assert!(state.is_tournament_in_progress());
+
+        // The survivors take their seats back, a stranger is queued
+        let mut effect = Effect::default();
+        state.handle_event(&mut effect, sync("alice", 0, 150))?;
+        state.handle_event(&mut effect, sync("dave", 2, 150))?;
+        assert!(!effect.start_game);
+        assert!(state.waiting_players.contains_key("dave"));
+        state.handle_event(&mut effect, sync("bob", 1, 150))?;
+        assert!(effect.start_game);
+        assert_eq!(state.num_of_players, 2);
+
+        // Settled with the original buy-in
+        let mut effect = Effect::default();
+        finish_game(&mut state, &["bob", "alice"], &mut effect);
+        assert_eq!(
+            effect.settles,
+            vec![
+                Settle::add("bob", 300),
+                Settle::sub("alice", 150),
+                Settle::sub("carol", 150),
+                Settle::eject("carol"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_series_standings_break_ties_by_rank() {
+        let mut state = game_with_players(&["alice", "bob", "carol"]);
+        state.penalty_points.insert("alice".into(), 1);
+        state.penalty_points.insert("bob".into(), 1);
+        state.players.get_mut("carol").unwrap().set_rank(0);
+        state.players.get_mut("bob").unwrap().set_rank(1);
+        assert_eq!(state.series_standings(), vec!["carol", "bob", "alice"]);
+    }
+
+    #[test]
+    fn test_payout_shares() {
+        assert_eq!(PayoutRule::WinnerTakesAll.shares(100, 3), vec![100, 0, 0]);
+        assert_eq!(PayoutRule::EqualShares.shares(100, 3), vec![34, 33, 33]);
+        assert_eq!(PayoutRule::EqualShares.shares(2, 3), vec![1, 1, 0]);
+        let rule = PayoutRule::RankWeighted(vec![3, 1]);
+        assert_eq!(rule.shares(10, 3), vec![8, 2, 0]);
+        assert_eq!(rule.shares(10, 1), vec![10]);
+        assert!(!PayoutRule::RankWeighted(vec![0, 1]).is_valid());
+        assert!(!PayoutRule::RankWeighted(vec![]).is_valid());
+    }
+
+    #[test]
+    fn test_settles_sum_to_zero() {
+        let rules = [
+            PayoutRule::WinnerTakesAll,
+            PayoutRule::EqualShares,
+            PayoutRule::RankWeighted(vec![5, 3, 1]),
+            PayoutRule::RankWeighted(vec![2]),
+        ];
+        let rakes = [
+            None,
+            Some(Rake {
+                slot_id: 0,
+                bps: 333,
+                cap: 0,
+            }),
+            Some(Rake {
+                slot_id: 0,
+                bps: 500,
+                cap: 3,
+            }),
+        ];
+        let addrs = ["alice", "bob", "carol", "dave"];
+        for n in 2..=addrs.len() {
+            for (rule, rake) in rules.iter().flat_map(|r| rakes.iter().map(move |k| (r, k))) {
+                for bet_amount in [0, 1, 2, 7, 100, 999_999, u64::MAX / 2] {
+                    let mut state = game_with_players(&addrs[..n]);
+                    state.stake = bet_amount;
+                    for p in state.players.values_mut() {
+                        p.balance = bet_amount;
+                    }
+                    state.payout_rule = rule.clone();
+                    state.rake = *rake;
+                    let mut effect = Effect::default();
+                    finish_game(&mut state, &addrs[..n], &mut effect);
+                    let mut sum: i128 = 0;
+                    for s in effect.settles.iter() {
+                        match s.op {
+                            SettleOp::Add(amt) => sum += amt as i128,
+                            SettleOp::Sub(amt) => sum -= amt as i128,
+                            _ => (),
+                        }
+                    }
+                    for t in effect.transfers.iter() {
+                        sum += t.amount as i128;
+                    }
+                    assert_eq!(
+                        sum, 0,
+                        "{} players, {:?}, {:?}, {}",
+                        n, rule, rake, bet_amount
+                    );
+                    assert_eq!(
+                        effect.settles.last(),
+                        Some(&Settle::sub(addrs[n - 1], bet_amount))
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rake_is_taken_from_winnings() {
+        let addrs = ["alice", "bob", "carol"];
+        let mut state = game_with_players(&addrs);
+        state.stake = 1000;
+        for p in state.players.values_mut() {
+            p.balance = 1000;
+        }
+        state.payout_rule = PayoutRule::EqualShares;
+        state.rake = Some(Rake {
+            slot_id: 1,
+            bps: 250,
+            cap: 20,
+        });
+        let mut effect = Effect::default();
+        finish_game(&mut state, &addrs, &mut effect);
+        assert_eq!(effect.transfers.len(), 1);
+        assert_eq!(effect.transfers[0].slot_id, 1);
+        assert_eq!(effect.transfers[0].amount, 20);
+        assert_eq!(
+            effect.settles,
+            vec![
+                Settle::add("alice", 490),
+                Settle::add("bob", 490),
+                Settle::sub("carol", 1000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kick_idle_players_after_countdown() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob", "carol"], 4, 2);
+        set_ready(&mut state, &["alice", "bob"]);
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert!(!effect.start_game);
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS));
+        assert_eq!(state.players["carol"].idle_deadline(), START_COUNTDOWN_MS);
+        assert_eq!(state.players["alice"].idle_deadline(), 0);
+
+        // The countdown is not restarted
+        let mut effect = effect_at(5_000);
+        state.try_start_game(&mut effect);
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS - 5_000));
+        assert_eq!(state.players["carol"].idle_deadline(), START_COUNTDOWN_MS);
+
+        let mut effect = effect_at(START_COUNTDOWN_MS);
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert_eq!(effect.settles, vec![Settle::eject("carol")]);
+        assert!(effect.start_game);
+        assert_eq!(state.stage, Stage::Shuffling);
+        assert_eq!(state.num_of_players, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unready_blocks_the_start() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob"], 2, 2);
+        let mut effect = Effect::default();
+        let res = state.handle_action(&mut effect, "alice".into(), Action::Unready);
+        assert_eq!(res.unwrap_err(), Error::NotReady.into());
+        state.handle_action(&mut effect, "alice".into(), Action::Ready)?;
+        state.handle_action(&mut effect, "alice".into(), Action::Unready)?;
+        assert!(!state.players["alice"].is_ready());
+
+        let mut effect = Effect::default();
+        state.handle_action(&mut effect, "bob".into(), Action::Ready)?;
+        assert!(!effect.start_game);
+
+        // Alice is kicked for being idle, Bob is left alone
+        let mut effect = effect_at(START_COUNTDOWN_MS);
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert_eq!(effect.settles, vec![Settle::eject("alice")]);
+        assert!(!effect.start_game);
+        assert_eq!(state.stage, Stage::Waiting);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kick_idle_player_alone_at_the_table() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice"], 4, 2);
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS));
+
+        let mut effect = effect_at(START_COUNTDOWN_MS);
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert_eq!(effect.settles, vec![Settle::eject("alice")]);
+        assert!(state.players.is_empty());
+
+        // Nobody is idle while waiting for more players
+        let mut state = waiting_table(&["alice"], 4, 2);
+        set_ready(&mut state, &["alice"]);
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert_eq!(effect.wait_timeout, None);
+        state.handle_action(&mut effect, "alice".into(), Action::Unready)?;
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS));
+        Ok(())
+    }
+
+    #[test]
+    fn test_late_joiner_has_own_countdown() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob"], 3, 2);
+        set_ready(&mut state, &["alice"]);
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS));
+
+        // Carol sits down just before Bob's countdown ends
+        let mut effect = effect_at(14_900);
+        state.handle_event(&mut effect, sync("carol", 2, 100))?;
+        assert_eq!(effect.wait_timeout, Some(100));
+        assert_eq!(state.players["carol"].idle_deadline(), 29_900);
+
+        // Only Bob is kicked
+        let mut effect = effect_at(START_COUNTDOWN_MS);
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert_eq!(effect.settles, vec![Settle::eject("bob")]);
+        assert!(!effect.start_game);
+        assert_eq!(effect.wait_timeout, Some(14_900));
+        assert!(state.players.contains_key("carol"));
+
+        // Carol gets ready, the game starts short-handed after the
+        // start countdown
+        let mut effect = effect_at(20_000);
+        state.handle_action(&mut effect, "carol".into(), Action::Ready)?;
+        assert!(!effect.start_game);
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS));
+        let mut effect = effect_at(20_000 + START_COUNTDOWN_MS);
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert!(effect.settles.is_empty());
+        assert!(effect.start_game);
+        assert_eq!(state.num_of_players, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_start_when_all_players_are_ready() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob"], 2, 2);
+        let mut effect = Effect::default();
+        state.handle_action(&mut effect, "alice".into(), Action::Ready)?;
+        assert!(!effect.start_game);
+        let res = state.handle_action(&mut effect, "alice".into(), Action::Ready);
+        assert_eq!(res.unwrap_err(), Error::DuplicatedReady.into());
+
+        let mut effect = Effect::default();
+        state.handle_action(&mut effect, "bob".into(), Action::Ready)?;
+        assert!(effect.start_game);
+        assert_eq!(state.num_of_players, 2);
+        let res = state.handle_action(&mut effect, "bob".into(), Action::Ready);
+        assert_eq!(
+            res.unwrap_err(),
+            Error::InvalidStage(Stage::Shuffling).into()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_start_short_handed_after_countdown() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob"], 4, 2);
+        set_ready(&mut state, &["alice"]);
+        let mut effect = effect_at(1_000);
+        state.handle_action(&mut effect, "bob".into(), Action::Ready)?;
+        assert!(!effect.start_game);
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS));
+        assert_eq!(state.timeout, 1_000 + START_COUNTDOWN_MS);
+
+        // Carol takes a free seat in time
+        let mut effect = effect_at(10_000);
+        state.handle_event(&mut effect, sync("carol", 2, 100))?;
+        state.handle_action(&mut effect, "carol".into(), Action::Ready)?;
+        assert!(!effect.start_game);
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS - 9_000));
+
+        let mut effect = effect_at(1_000 + START_COUNTDOWN_MS);
+        state.handle_event(&mut effect, Event::WaitingTimeout)?;
+        assert!(effect.settles.is_empty());
+        assert!(effect.start_game);
+        assert_eq!(state.num_of_players, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invariants() {
+        let state = acting_game();
+        assert!(state.check_invariants().is_ok());
+
+        let mut state = acting_game();
+        state.players.get_mut("alice").unwrap().card_idxs.push(6);
+        assert!(violated(&state).contains("card 6 is duplicated"));
+
+        let mut state = acting_game();
+        state.attacks.push(Attack::new(20));
+        assert!(violated(&state).contains("card 20 is not dealt"));
+
+        let mut state = acting_game();
+        let def = state.get_player_by_role(Role::Defender).unwrap().addr();
+        state.players.get_mut(&def).unwrap().set_role(None);
+        assert!(violated(&state).contains("expect 1 Defender, got 0"));
+
+        // Three players are in the round without a co-attacker
+        let mut state = acting_game();
+        let co = state.get_player_by_role(Role::CoAttacker).unwrap().addr();
+        state.players.get_mut(&co).unwrap().set_role(None);
+        assert!(violated(&state).contains("expect 1 CoAttacker, got 0"));
+
+        // The co-attacker escaped in current round keeps the role
+        let mut state = acting_game();
+        let co = state.get_player_by_role(Role::CoAttacker).unwrap().addr();
+        let p = state.players.get_mut(&co).unwrap();
+        p.card_idxs.clear();
+        p.set_rank(0);
+        state.num_of_finished = 1;
+        assert!(state.check_invariants().is_ok());
+
+        let mut state = acting_game();
+        state.attack_space = 2;
+        assert!(violated(&state).contains("attack space 2, expect 6"));
+
+        let mut state = acting_game();
+        state.num_of_finished = 1;
+        assert!(violated(&state).contains("1 players finished, but 0 ranked"));
+    }
+
+    #[test]
+    fn test_forward_onto_short_hand_is_rejected() -> anyhow::Result<()> {
+        let mut state = acting_game();
+        state.deck_offset = DECK_LEN;
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let next = state.next_defender_on_forward(false)?.unwrap().addr();
+        // The next defender has a single card left at the end of the
+        // deck
+        state.players.get_mut(&next).unwrap().card_idxs.truncate(1);
+        let custom = |sender: &str, action: Action| Event::Custom {
+            sender: sender.into(),
+            raw: action.try_to_vec().unwrap(),
+        };
+        let secrets_ready = Event::SecretsReady {
+            random_ids: vec![state.random_id],
+        };
+
+        let open = Card::new(state.players[&att].card_idxs[0], "s6".into());
+        let attack = Action::Attack {
+            cards: vec![open.clone()],
+        };
+        state.handle_event(&mut revealed(&state, &[]), custom(&att, attack))?;
+        state.handle_event(&mut revealed(&state, &[&open]), secrets_ready)?;
+        assert!(state.attacks[0].is_open());
+
+        // Two attacks can't be forwarded onto one card
+        let card = Card::new(state.players[&def].card_idxs[0], "d6".into());
+        state.displays.clear();
+        let snapshot = state.try_to_vec()?;
+        let forward = Action::Forward { card };
+        let res = state.handle_event(&mut revealed(&state, &[]), custom(&def, forward));
+        assert_eq!(res.unwrap_err(), Error::CantForward.into());
+        assert_eq!(state.try_to_vec()?, snapshot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_over_attack_after_take_is_rejected() -> anyhow::Result<()> {
+        let mut state = acting_game();
+        state.deck_offset = DECK_LEN;
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        // The defender has two cards left at the end of the deck
+        state.players.get_mut(&def).unwrap().card_idxs.truncate(2);
+        state.update_attack_space()?;
+        let custom = |sender: &str, action: Action| Event::Custom {
+            sender: sender.into(),
+            raw: action.try_to_vec().unwrap(),
+        };
+        let secrets_ready = Event::SecretsReady {
+            random_ids: vec![state.random_id],
+        };
+
+        let a = state.players[&att].card_idxs.clone();
+        let open = Card::new(a[0], "s6".into());
+        let attack = Action::Attack {
+            cards: vec![open.clone()],
+        };
+        state.handle_event(&mut revealed(&state, &[]), custom(&att, attack))?;
+        state.handle_event(&mut revealed(&state, &[&open]), secrets_ready)?;
+        state.handle_event(&mut revealed(&state, &[&open]), custom(&def, Action::Take))?;
+        assert_eq!(state.stage, Stage::EndOfRound);
+        assert_eq!(state.attack_space, 1);
+
+        // Two more cards are one too many for the hand of the defender
+        let cards = vec![Card::new(a[1], "c6".into()), Card::new(a[2], "h6".into())];
+        state.displays.clear();
+        let snapshot = state.try_to_vec()?;
+        let attack = Action::Attack { cards };
+        let res = state.handle_event(&mut revealed(&state, &[&open]), custom(&att, attack));
+        assert_eq!(res.unwrap_err(), Error::NoAttackSpace(1, 2).into());
+        assert_eq!(state.try_to_vec()?, snapshot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_attacks_keep_the_state() {
+        let (mut state, _) = forwarding_game();
+        let att = state.get_player_by_role(Role::Attacker).unwrap();
+        let (att, a) = (att.addr(), att.card_idxs.clone());
+        let coatt = state.get_player_by_role(Role::CoAttacker).unwrap();
+        let (coatt, c) = (coatt.addr(), coatt.card_idxs.clone());
+        let attack = |idxs: &[usize], value: &str| Action::Attack {
+            cards: idxs.iter().map(|i| Card::new(*i, value.into())).collect(),
+        };
+        let coattack = |cards: Vec<Card>| Action::CoAttack { cards };
+
+        let err = rejected(&mut state, &att, attack(&[0, 1, 2, 3, 4, 5], "d6"));
+        assert_eq!(err, Error::NoAttackSpace(5, 6).into());
+        let err = rejected(&mut state, &att, attack(&a[..1], "d7"));
+        assert_eq!(err, Error::NotValidAttackCard.into());
+        let err = rejected(&mut state, &coatt, attack(&c[..1], "d6"));
+        assert_eq!(err, Error::PlayerIsNotAttacker.into());
+        let err = rejected(&mut state, &att, attack(&[30], "d6"));
+        assert_eq!(err, Error::InvalidCardIndex(30).into());
+        let err = rejected(&mut state, &att, attack(&[a[0], a[0]], "d6"));
+        assert_eq!(err, Error::InvalidCardIndex(a[0]).into());
+
+        let cards = vec![Card::new(c[0], "d6".into())];
+        let err = rejected(&mut state, &att, coattack(cards));
+        assert_eq!(err, Error::PlayerIsNotCoAttacker.into());
+        // The first card is valid, but the second is not in hand
+        let cards = vec![Card::new(c[0], "d6".into()), Card::new(31, "c6".into())];
+        let err = rejected(&mut state, &coatt, coattack(cards));
+        assert_eq!(err, Error::InvalidCardIndex(31).into());
+
+        state.stage = Stage::Dealing;
+        let err = rejected(&mut state, &att, attack(&a[..1], "d6"));
+        assert_eq!(err, Error::CantAttack.into());
+    }
+
+    #[test]
+    fn test_rejected_defends_keep_the_state() {
+        let (mut state, _) = forwarding_game();
+        let att = state.get_player_by_role(Role::Attacker).unwrap().addr();
+        let def = state.get_player_by_role(Role::Defender).unwrap();
+        let (def, d) = (def.addr(), def.card_idxs.clone());
+        let defend = |idx: usize, value: &str, target: u8| Action::Defend {
+            card: Card::new(idx, value.into()),
+            target,
+        };
+
+        // The card of the defender is not taken by others
+        let err = rejected(&mut state, &att, defend(d[0], "s7", 0));
+        assert_eq!(err, Error::PlayerIsNotDefender.into());
+        let err = rejected(&mut state, &def, defend(30, "s7", 0));
+        assert_eq!(err, Error::InvalidCardIndex(30).into());
+        let err = rejected(&mut state, &def, defend(d[0], "s7", 3));
+        assert_eq!(err, Error::InvalidAttackIndex(3).into());
+        let err = rejected(&mut state, &def, defend(d[0], "d7", 0));
+        assert_eq!(err, Error::InvalidDefendCard.into());
+
+        let open = Card::new(state.attacks[0].card_idxs()[0], "s6".into());
+        let close = Card::new(d[1], "s7".into());
+        state.attacks = vec![Attack::Closed { open, close }];
+        let err = rejected(&mut state, &def, defend(d[0], "s8", 0));
+        assert_eq!(err, Error::InvalidAttackStatus.into());
+
+        state.stage = Stage::EndOfRound;
+        let err = rejected(&mut state, &def, defend(d[0], "s8", 0));
+        assert_eq!(err, Error::CantDefend.into());
+    }
+
+    #[test]
+    fn test_defend_many() -> anyhow::Result<()> {
+        let (mut state, _) = forwarding_game();
+        let att = state.get_player_by_role(Role::Attacker)?;
+        let a = att.card_idxs[0];
+        let def = state.get_player_by_role(Role::Defender)?;
+        let (def, d) = (def.addr(), def.card_idxs.clone());
+        state.get_player_by_role_mut(Role::Attacker)?.take_card(a)?;
+        state.attacks.push(Attack::Open {
+            open: Card::new(a, "c7".into()),
+        });
+        let defense = |idx: usize, value: &str, target: u8| Defense {
+            card: Card::new(idx, value.into()),
+            target,
+        };
+        let defend_many = |defenses| Action::DefendMany { defenses };
+
+        // The defenses are rejected together
+        let defenses = vec![defense(d[0], "s8", 0), defense(d[1], "c6", 1)];
+        let err = rejected(&mut state, &def, defend_many(defenses));
+        assert_eq!(err, Error::InvalidDefendCard.into());
+        let defenses = vec![defense(d[0], "s8", 0), defense(d[0], "c8", 1)];
+        let err = rejected(&mut state, &def, defend_many(defenses));
+        assert_eq!(err, Error::InvalidCardIndex(d[0]).into());
+        let defenses = vec![defense(d[0], "s8", 0), defense(d[1], "s9", 0)];
+        let err = rejected(&mut state, &def, defend_many(defenses));
+        assert_eq!(err, Error::InvalidAttackIndex(0).into());
+        let err = rejected(&mut state, &def, defend_many(vec![]));
+        assert_eq!(err, Error::InvalidDefendCard.into());
+
+        // Both cards are revealed at once
+        let defenses = vec![defense(d[0], "s8", 0), defense(d[1], "h6", 1)];
+        let mut effect = revealed(&state, &[]);
+        state.handle_action(&mut effect, def.clone(), defend_many(defenses))?;
+        assert_eq!(effect.reveals.len(), 1);
+        assert_eq!(effect.reveals[0].indexes, vec![d[0], d[1]]);
+        assert!(state
+            .attacks
+            .iter()
+            .all(|a| matches!(a, Attack::ConfirmClose { .. })));
+        assert_eq!(state.players[&def].card_idxs.len(), d.len() - 2);
+
+        // The second card is a six of clubs, which can't beat the seven
+        let close = Card::new(d[0], "s8".into());
+        let lie = Card::new(d[1], "c6".into());
+        let mut effect = revealed(&state, &[&close, &lie]);
+        state.update_attacks(&mut effect)?;
+        assert!(state.attacks[0].is_closed());
+        assert!(state.attacks[1].is_open());
+        let p = &state.players[&def];
+        assert!(p.card_idxs.contains(&d[1]));
+        assert!(p.known_cards.is_empty());
+        assert_eq!(state.stage, Stage::Acting);
+        assert!(state.check_invariants().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_ends_when_all_throwers_pass() -> anyhow::Result<()> {
+        let (mut state, _) = forwarding_game();
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let coatt = state.get_player_by_role(Role::CoAttacker)?.addr();
+        let num_of_cards = state.players[&def].card_idxs.len();
+
+        let err = rejected(&mut state, &att, Action::Pass);
+        assert_eq!(err, Error::InvalidStage(Stage::Acting).into());
+
+        let mut effect = Effect::default();
+        state.handle_action(&mut effect, def.clone(), Action::Take)?;
+        assert_eq!(state.stage, Stage::EndOfRound);
+        state.handle_action(&mut effect, att.clone(), Action::Pass)?;
+        assert_eq!(state.stage, Stage::EndOfRound);
+
+        let err = rejected(&mut state, &att, Action::Pass);
+        assert_eq!(err, Error::DuplicatedPass.into());
+        let err = rejected(&mut state, &def, Action::Pass);
+        assert_eq!(err, Error::PlayerIsNotAttacker.into());
+        let err = rejected(&mut state, &att, Action::Beated);
+        assert_eq!(err, Error::InvalidStage(Stage::EndOfRound).into());
+
+        // The last thrower passes, the defender takes the card
+        state.handle_action(&mut effect, coatt, Action::Pass)?;
+        assert!(round_ended(&state));
+        assert!(state.passed_addrs.is_empty());
+        assert_eq!(state.players[&def].card_idxs.len(), num_of_cards + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_ends_on_take_if_nobody_can_throw() -> anyhow::Result<()> {
+        let (mut state, _) = forwarding_game();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        for role in [Role::Attacker, Role::CoAttacker] {
+            state.get_player_by_role_mut(role)?.card_idxs.clear();
+        }
+        let mut effect = Effect::default();
+        state.handle_action(&mut effect, def, Action::Take)?;
+        assert!(round_ended(&state));
+        Ok(())
+    }
+
+    #[test]
+    fn test_drawing_order() -> anyhow::Result<()> {
+        let mut state = game_with_players(&["alice", "bob", "carol", "dave"]);
+        state.trump = Some(Card::new(TRUMP_IDX, "h2".into()));
+        state.init_roles()?;
+        let mut order = state.players_in_position_order_mut()?;
+        let first = order
+            .iter()
+            .position(|p| p.role == Some(Role::Attacker))
+            .unwrap();
+        order.rotate_left(first);
+        let order: Vec<String> = order.into_iter().map(|p| p.addr()).collect();
+        // Attacker, co-attacker, the one without role, then defender
+        let expected = vec![
+            order[0].clone(),
+            order[2].clone(),
+            order[3].clone(),
+            order[1].clone(),
+        ];
+        assert_eq!(state.drawing_order()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_defender_draws_last_when_deck_runs_out() -> anyhow::Result<()> {
+        let mut state = acting_game();
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let coatt = state.get_player_by_role(Role::CoAttacker)?.addr();
+        for p in state.players.values_mut() {
+            p.card_idxs.truncate(4);
+        }
+        // Two cards and the trump are left
+        state.deck_offset = DECK_LEN - 3;
+        let mut effect = Effect::default();
+        state.end_round(false, &mut effect)?;
+        assert_eq!(dealt_addrs(&state), vec![att.clone(), coatt.clone()]);
+        assert_eq!(state.players[&att].card_idxs.len(), 6);
+        assert_eq!(state.players[&coatt].card_idxs.len(), 5);
+        assert_eq!(state.players[&def].card_idxs.len(), 4);
+        assert_eq!(state.trump_holder, Some(coatt));
+        assert!(state.is_deck_empty());
+        assert_eq!(state.stage, Stage::Dealing);
+        // The roles are rotated after drawing
+        assert_eq!(state.get_player_by_role(Role::Attacker)?.addr(), def);
+        Ok(())
+    }
+
+    #[test]
+    fn test_acting_after_only_the_trump_is_drawn() -> anyhow::Result<()> {
+        let mut state = acting_game();
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        state.players.get_mut(&att).unwrap().card_idxs.truncate(5);
+        state.deck_offset = DECK_LEN - 1;
+        let mut effect = Effect::default();
+        state.end_round(false, &mut effect)?;
+        assert_eq!(state.trump_holder, Some(att));
+        assert_eq!(state.stage, Stage::Acting);
+        assert_eq!(state.get_player_by_role(Role::Attacker)?.addr(), def);
+        assert_eq!(state.attack_space, 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_ends_once_after_defender_escapes() -> anyhow::Result<()> {
+        let mut state = acting_game();
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let coatt = state.get_player_by_role(Role::CoAttacker)?.addr();
+        // The defender beat the last attack with the last card
+        let open = state.players.get_mut(&att).unwrap().card_idxs.remove(0);
+        let p = state.players.get_mut(&def).unwrap();
+        let close = p.card_idxs.remove(0);
+        p.card_idxs.clear();
+        p.set_rank(0);
+        state.num_of_finished = 1;
+        state.deck_offset = DECK_LEN;
+        state.attacks = vec![Attack::Closed {
+            open: Card::new(open, "s6".into()),
+            close: Card::new(close, "s7".into()),
+        }];
+
+        let mut effect = Effect::default();
+        state.end_round(false, &mut effect)?;
+        let num_of_ended = state
+            .displays
+            .iter()
+            .filter(|d| matches!(d, Display::RoundEnded { .. }))
+            .count();
+        assert_eq!(num_of_ended, 1);
+        assert_eq!(state.stage, Stage::Acting);
+        assert!(state.attacks.is_empty());
+        assert_eq!(state.players[&def].role(), None);
+        assert_eq!(state.get_player_by_role(Role::Attacker)?.addr(), coatt);
+        assert_eq!(state.get_player_by_role(Role::Defender)?.addr(), att);
+        assert_eq!(state.attack_space, 5);
+        assert_eq!(effect.action_timeout.map(|t| t.player_addr), Some(coatt));
+        assert!(state.check_invariants().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_defender_draws_last_after_taking() -> anyhow::Result<()> {
+        let (mut state, _) = forwarding_game();
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let coatt = state.get_player_by_role(Role::CoAttacker)?.addr();
+        state.players.get_mut(&coatt).unwrap().card_idxs.truncate(5);
+        state.players.get_mut(&def).unwrap().card_idxs.truncate(3);
+        // The attacker draws the last card, the trump is left
+        state.deck_offset = DECK_LEN - 2;
+        state.stage = Stage::EndOfRound;
+        let mut effect = Effect::default();
+        state.end_round(true, &mut effect)?;
+        assert_eq!(dealt_addrs(&state), vec![att.clone(), coatt.clone()]);
+        assert_eq!(state.players[&att].card_idxs.len(), 6);
+        assert_eq!(state.players[&def].card_idxs.len(), 4);
+        assert_eq!(state.trump_holder, Some(coatt.clone()));
+        assert_eq!(state.stage, Stage::Dealing);
+        // The defender took the cards, the co-attacker attacks next
+        assert_eq!(state.get_player_by_role(Role::Attacker)?.addr(), coatt);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_takes_and_beateds_keep_the_state() {
+        let (mut state, _) = forwarding_game();
+        let att = state.get_player_by_role(Role::Attacker).unwrap().addr();
+        let def = state.get_player_by_role(Role::Defender).unwrap().addr();
+
+        let err = rejected(&mut state, &att, Action::Take);
+        assert_eq!(err, Error::PlayerIsNotDefender.into());
+        let err = rejected(&mut state, &def, Action::Beated);
+        assert_eq!(err, Error::PlayerIsNotAttacker.into());
+
+        state.beated_addrs = vec![att.clone()];
+        let err = rejected(&mut state, &att, Action::Beated);
+        assert_eq!(err, Error::DuplicatedBeated.into());
+
+        state.beated_addrs.clear();
+        state.stage = Stage::EndOfGame;
+        let err = rejected(&mut state, &def, Action::Take);
+        assert_eq!(err, Error::InvalidStage(Stage::EndOfGame).into());
+        let err = rejected(&mut state, &att, Action::Beated);
+        assert_eq!(err, Error::InvalidStage(Stage::EndOfGame).into());
+
+        state.stage = Stage::Acting;
+        state.attacks.push(Attack::new(30));
+        let err = rejected(&mut state, &def, Action::Take);
+        assert_eq!(err, Error::UnconfirmedCard.into());
+        let err = rejected(&mut state, &att, Action::Beated);
+        assert_eq!(err, Error::UnconfirmedCard.into());
+    }
+
+    #[test]
+    fn test_forward() -> anyhow::Result<()> {
+        let (mut state, card) = forwarding_game();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let coatt = state.get_player_by_role(Role::CoAttacker)?.addr();
+        let mut effect = Effect {
+            revealed: HashMap::from([(state.random_id, HashMap::new())]),
+            ..Default::default()
+        };
+        state.handle_action(
+            &mut effect,
+            def.clone(),
+            Action::Forward { card: card.clone() },
+        )?;
+        // Nothing is changed until the card is revealed
+        assert_eq!(effect.reveals[0].indexes, vec![card.idx]);
+        assert_eq!(state.attacks.len(), 1);
+        assert!(state.players[&def].card_idxs.contains(&card.idx));
+        assert_eq!(state.get_player_by_role(Role::Defender)?.addr(), def);
+        let res = state.handle_action(
+            &mut effect,
+            def.clone(),
+            Action::Forward { card: card.clone() },
+        );
+        assert_eq!(res.unwrap_err(), Error::UnconfirmedCard.into());
+
+        let mut effect = revealed(&state, &[&card]);
+        state.update_attacks(&mut effect)?;
+        assert!(state.forwarding_card.is_none());
+        assert_eq!(state.attacks.len(), 2);
+        assert!(state.attacks.iter().all(Attack::is_open));
+        assert!(!state.players[&def].card_idxs.contains(&card.idx));
+        assert_eq!(state.get_player_by_role(Role::Attacker)?.addr(), def);
+        assert_eq!(state.get_player_by_role(Role::Defender)?.addr(), coatt);
+        Ok(())
+    }
+
+    #[test]
+    fn test_escape_by_forwarding_the_last_card() -> anyhow::Result<()> {
+        let (mut state, card) = forwarding_game();
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let coatt = state.get_player_by_role(Role::CoAttacker)?.addr();
+        state.deck_offset = DECK_LEN;
+        state.players.get_mut(&def).unwrap().card_idxs = vec![card.idx];
+        let mut effect = revealed(&state, &[&card]);
+        state.handle_action(&mut effect, def.clone(), Action::Forward { card })?;
+
+        // The escaped defender doesn't keep the role
+        assert_eq!(state.players[&def].rank(), Some(0));
+        assert_eq!(state.players[&def].role(), None);
+        assert_eq!(state.get_player_by_role(Role::Attacker)?.addr(), coatt);
+        assert_eq!(state.get_player_by_role(Role::Defender)?.addr(), att);
+        let num_of_defenders = state
+            .players
+            .values()
+            .filter(|p| p.role() == Some(Role::Defender))
+            .count();
+        assert_eq!(num_of_defenders, 1);
+        assert!(state.check_invariants().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_is_checked_before_any_change() {
+        let (mut state, card) = forwarding_game();
+        let att = state.get_player_by_role(Role::Attacker).unwrap().addr();
+        let def = state.get_player_by_role(Role::Defender).unwrap().addr();
+        let coatt = state.get_player_by_role(Role::CoAttacker).unwrap().addr();
+
+        let err = forward_rejected(&mut state, &att, card.clone());
+        assert_eq!(err, Error::PlayerIsNotDefender.into());
+
+        let err = forward_rejected(&mut state, &def, Card::new(30, "d6".into()));
+        assert_eq!(err, Error::InvalidCardIndex(30).into());
+
+        let other = Card::new(card.idx, "d7".into());
+        let err = forward_rejected(&mut state, &def, other);
+        assert_eq!(err, Error::InvalidForwardCard.into());
+
+        // The next defender can't cover two attacks
+        let (mut state, card) = forwarding_game();
+        state.players.get_mut(&coatt).unwrap().card_idxs.truncate(1);
+        let err = forward_rejected(&mut state, &def, card);
+        assert_eq!(err, Error::CantForward.into());
+
+        // An attack is already beaten
+        let (mut state, card) = forwarding_game();
+        let open = Card::new(state.attacks[0].card_idxs()[0], "s6".into());
+        let close = Card::new(state.players[&def].card_idxs[1], "s7".into());
+        state.attacks = vec![Attack::Closed { open, close }];
+        let err = forward_rejected(&mut state, &def, card);
+        assert_eq!(err, Error::CantForward.into());
+
+        let (mut state, card) = forwarding_game();
+        state.stage = Stage::EndOfRound;
+        let err = forward_rejected(&mut state, &def, card);
+        assert_eq!(err, Error::CantForward.into());
+    }
+
+    #[test]
+    fn test_forward_in_heads_up_game() {
+        let mut state = game_with_players(&["alice", "bob"]);
+        state.stage = Stage::Acting;
+        state.deck_offset = 10;
+        state.trump = Some(Card::new(TRUMP_IDX, "h2".into()));
+        state.init_roles().unwrap();
+        let att = state.get_player_by_role(Role::Attacker).unwrap().addr();
+        let def = state.get_player_by_role(Role::Defender).unwrap().addr();
+        state.players.get_mut(&att).unwrap().card_idxs = vec![0];
+        state.players.get_mut(&def).unwrap().card_idxs = vec![1, 2, 3];
+        state.attacks = vec![Attack::Open {
+            open: Card::new(4, "s6".into()),
+        }];
+        // The attacker has one card left for two attacks
+        let err = forward_rejected(&mut state, &def, Card::new(1, "d6".into()));
+        assert_eq!(err, Error::CantForward.into());
+
+        state.players.get_mut(&att).unwrap().card_idxs = vec![0, 5];
+        assert!(state
+            .check_forward(&def, &Card::new(1, "d6".into()), true)
+            .is_ok());
+
+        // The defender escapes by forwarding the last card
+        state.deck_offset = DECK_LEN;
+        state.players.get_mut(&def).unwrap().card_idxs = vec![1];
+        let card = Card::new(1, "d6".into());
+        let mut effect = revealed(&state, &[&card]);
+        let action = Action::Forward { card };
+        state
+            .handle_action(&mut effect, def.clone(), action)
+            .unwrap();
+        assert!(state.is_game_over());
+        assert_eq!(state.players[&def].rank(), Some(0));
+    }
+
+    #[test]
+    fn test_forward_by_showing_trump() {
+        let (state, card) = forwarding_game();
+        let def = state.get_player_by_role(Role::Defender).unwrap().addr();
+        let trump = Card::new(card.idx, "h6".into());
+        assert!(state.check_forward(&def, &trump, false).is_ok());
+        // Only a trump can be shown
+        let res = state.check_forward(&def, &card, false);
+        assert_eq!(res.unwrap_err(), Error::InvalidForwardCard.into());
+
+        // The card is kept in hand
+        let mut state = state;
+        state.forward(&trump, false).unwrap();
+        assert_eq!(state.attacks.len(), 1);
+        assert_eq!(
+            state.get_player_by_role(Role::Attacker).unwrap().addr(),
+            def
+        );
+        assert!(state.players[&def].card_idxs.contains(&card.idx));
+    }
+
+    #[test]
+    fn test_show_trump_to_forward() -> anyhow::Result<()> {
+        let (mut state, card) = forwarding_game();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let coatt = state.get_player_by_role(Role::CoAttacker)?.addr();
+        let trump = Card::new(card.idx, "h6".into());
+        let action = Action::ShowTrumpForward {
+            card: trump.clone(),
+        };
+        let mut effect = revealed(&state, &[]);
+        state.handle_action(&mut effect, def.clone(), action.clone())?;
+        assert_eq!(effect.reveals[0].indexes, vec![trump.idx]);
+        assert_eq!(state.showing_trump_idx, Some(trump.idx));
+        assert_eq!(state.get_player_by_role(Role::Defender)?.addr(), def);
+
+        let res = state.handle_action(&mut effect, def.clone(), action);
+        assert_eq!(res.unwrap_err(), Error::UnconfirmedCard.into());
+
+        // Forward once the trump is revealed, the card is kept in hand
+        let mut effect = revealed(&state, &[&trump]);
+        state.update_attacks(&mut effect)?;
+        assert_eq!(state.showing_trump_idx, None);
+        assert_eq!(state.shown_trump_idxs, vec![trump.idx]);
+        assert_eq!(state.attacks.len(), 1);
+        assert_eq!(state.get_player_by_role(Role::Attacker)?.addr(), def);
+        assert_eq!(state.get_player_by_role(Role::Defender)?.addr(), coatt);
+        let p = &state.players[&def];
+        assert!(p.card_idxs.contains(&trump.idx));
+        assert!(p.known_cards.iter().any(|c| c.idx == trump.idx));
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_trump_once_per_round() -> anyhow::Result<()> {
+        let (mut state, card) = forwarding_game();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        state.shown_trump_idxs = vec![card.idx];
+        let action = Action::ShowTrumpForward {
+            card: Card::new(card.idx, "h6".into()),
+        };
+        let mut effect = revealed(&state, &[]);
+        let res = state.handle_action(&mut effect, def, action);
+        assert_eq!(res.unwrap_err(), Error::DuplicatedShownTrump.into());
+
+        state.end_round(false, &mut effect)?;
+        assert!(state.shown_trump_idxs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_fake_trump_is_ignored() -> anyhow::Result<()> {
+        let (mut state, card) = forwarding_game();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let action = Action::ShowTrumpForward {
+            card: Card::new(card.idx, "h6".into()),
+        };
+        let mut effect = revealed(&state, &[]);
+        state.handle_action(&mut effect, def.clone(), action)?;
+
+        // It's a six of diamonds actually
+        let mut effect = revealed(&state, &[&card]);
+        state.update_attacks(&mut effect)?;
+        assert_eq!(state.showing_trump_idx, None);
+        assert!(state.shown_trump_idxs.is_empty());
+        assert_eq!(state.get_player_by_role(Role::Defender)?.addr(), def);
+        assert!(state.players[&def].known_cards.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lying_thrower_takes_back_the_card() -> anyhow::Result<()> {
+        let (mut state, _) = forwarding_game();
+        let coatt = state.get_player_by_role(Role::CoAttacker)?.addr();
+        let idx = state.players[&coatt].card_idxs[0];
+        let declared = Card::new(idx, "d6".into());
+        let action = Action::CoAttack {
+            cards: vec![declared],
+        };
+        state.handle_action(&mut revealed(&state, &[]), coatt.clone(), action)?;
+        assert_eq!(state.attacks.len(), 2);
+
+        // It's a nine of diamonds actually
+        let card = Card::new(idx, "d9".into());
+        let mut effect = revealed(&state, &[&card]);
+        state.update_attacks(&mut effect)?;
+        assert_eq!(state.attacks.len(), 1);
+        assert!(state.declared_cards.is_empty());
+        let p = &state.players[&coatt];
+        assert!(p.card_idxs.contains(&idx));
+        assert!(p.known_cards.is_empty());
+        assert_eq!(state.stage, Stage::Acting);
+        assert!(state.check_invariants().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lying_defender_takes_back_the_card() -> anyhow::Result<()> {
+        let (mut state, card) = forwarding_game();
+        let def = state.get_player_by_role(Role::Defender)?.addr();
+        let action = Action::Defend {
+            card: Card::new(card.idx, "s7".into()),
+            target: 0,
+        };
+        state.handle_action(&mut revealed(&state, &[]), def.clone(), action)?;
+
+        // It's a six of diamonds, which can't beat the six of spades,
+        // the attack is open again
+        let mut effect = revealed(&state, &[&card]);
+        state.update_attacks(&mut effect)?;
+        assert!(state.attacks[0].is_open());
+        let p = &state.players[&def];
+        assert!(p.card_idxs.contains(&card.idx));
+        assert!(p.known_cards.is_empty());
+        assert_eq!(state.stage, Stage::Acting);
+        let att = state.get_player_by_role(Role::Attacker)?.addr();
+        assert_eq!(effect.action_timeout.map(|t| t.player_addr), Some(att));
+        assert!(state.check_invariants().is_ok());
+
+        // The same for a forward, nothing is forwarded
+        let (mut state, card) = forwarding_game();
+        let action = Action::Forward {
+            card: Card::new(card.idx, "c6".into()),
+        };
+        let other = Card::new(card.idx, "c7".into());
+        state.handle_action(&mut revealed(&state, &[]), def.clone(), action)?;
+        let mut effect = revealed(&state, &[&other]);
+        state.update_attacks(&mut effect)?;
+        assert!(state.forwarding_card.is_none());
+        assert_eq!(state.attacks.len(), 1);
+        assert_eq!(state.get_player_by_role(Role::Defender)?.addr(), def);
+        let p = &state.players[&def];
+        assert!(p.card_idxs.contains(&card.idx));
+        assert!(p.known_cards.is_empty());
+        assert_eq!(state.stage, Stage::Acting);
+        Ok(())
+    }
+
+    #[test]
+    fn test_end_game_with_invalid_counts_is_an_error() {
+        let mut effect = Effect::default();
+        let mut state = game_with_players(&["alice", "bob"]);
+        state.num_of_finished = 3;
+        let res = state.maybe_end_game(&mut effect);
+        assert_eq!(res.unwrap_err(), Error::TooManyFinishedPlayers(3, 2).into());
+
+        state.num_of_players = 0;
+        state.num_of_finished = 0;
+        let res = state.maybe_end_game(&mut effect);
+        assert_eq!(res.unwrap_err(), Error::InvalidNumOfPlayers.into());
+
+        state.mode = GameMode::Tournament;
+        let res = state.settle_game(&mut effect);
+        assert_eq!(res.unwrap_err(), Error::InvalidNumOfPlayers.into());
+    }
+
+    #[test]
+    fn test_shuffle_before_dealing() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob"], 2, 2);
+        set_ready(&mut state, &["alice", "bob"]);
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert_eq!(state.stage, Stage::Shuffling);
+
+        // Nobody has a role until the trump is revealed, the cards
+        // are dealt after that
+        let mut effect = Effect::default();
+        state.handle_event(&mut effect, Event::GameStart { access_version: 1 })?;
+        assert_eq!(state.stage, Stage::Shuffling);
+        assert!(state.players.values().all(|p| p.role().is_none()));
+        state.stage = Stage::Dealing;
+        assert!(violated(&state).contains("expect 1 Attacker, got 0"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_late_joiner_waits_for_next_game() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob"], 3, 2);
+        state.stage = Stage::Acting;
+        state.deck_offset = 12;
+        state.players.get_mut("alice").unwrap().card_idxs = (0..6).collect();
+        state.players.get_mut("bob").unwrap().card_idxs = (6..12).collect();
+        state.trump = Some(Card::new(TRUMP_IDX, "h2".into()));
+        state.init_roles()?;
+        state.update_attack_space()?;
+        let mut effect = Effect::default();
+        let event = Event::Sync {
+            new_players: vec![PlayerJoin {
+                addr: "carol".into(),
+                position: 0,
+                balance: 100,
+                access_version: 1,
+                verify_key: "".into(),
+            }],
+            new_servers: vec![],
+            transactor_addr: "".into(),
+            access_version: 1,
+        };
+        state.handle_event(&mut effect, event)?;
+        assert!(!effect.start_game);
+        assert_eq!(state.players.len(), 2);
+        assert!(state.waiting_players.contains_key("carol"));
+
+        // Carol takes a free seat after the rematch phase
+        state.stage = Stage::EndOfGame;
+        state.rematch_addrs = vec!["alice".into(), "bob".into()];
+        let mut effect = Effect::default();
+        state.end_rematch(&mut effect);
+        assert!(state.waiting_players.is_empty());
+        assert_eq!(state.players["carol"].position(), 2);
+        assert!(!state.players["carol"].is_ready());
+        assert!(!effect.start_game);
+        assert_eq!(effect.wait_timeout, Some(START_COUNTDOWN_MS));
+        Ok(())
+    }
+
+    #[test]
+    fn test_queue_joiners_at_a_full_table() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob"], 2, 2);
+        assert_eq!(state.free_seat(0), None);
+        state.add_player(Player::new("carol".into(), 5, 100));
+        assert_eq!(state.players.len(), 2);
+        assert!(state.waiting_players.contains_key("carol"));
+
+        // Carol takes the seat left by Bob
+        let mut effect = Effect::default();
+        state.handle_event(
+            &mut effect,
+            Event::Leave {
+                player_addr: "bob".into(),
+            },
+        )?;
+        assert!(state.waiting_players.is_empty());
+        assert_eq!(state.players["carol"].position(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_and_swap_seats() -> anyhow::Result<()> {
+        let mut state = waiting_table(&["alice", "bob"], 3, 2);
+        let mut effect = Effect::default();
+        let res = state.handle_action(
+            &mut effect,
+            "alice".into(),
+            Action::RequestSeat { position: 3 },
+        );
+        assert_eq!(res.unwrap_err(), Error::InvalidSeat(3).into());
+
+        // Take the free seat
+        state.handle_action(
+            &mut effect,
+            "alice".into(),
+            Action::RequestSeat { position: 2 },
+        )?;
+        assert_eq!(state.players["alice"].position(), 2);
+        assert_eq!(state.free_seat(1), Some(0));
+
+        // Swap when both sides request
+        state.handle_action(
+            &mut effect,
+            "bob".into(),
+            Action::RequestSeat { position: 2 },
+        )?;
+        assert_eq!(state.players["bob"].position(), 1);
+        assert_eq!(state.players["bob"].seat_request(), Some(2));
+        state.handle_action(
+            &mut effect,
+            "alice".into(),
+            Action::RequestSeat { position: 1 },
+        )?;
+        assert_eq!(state.players["alice"].position(), 1);
+        assert_eq!(state.players["bob"].position(), 2);
+        assert_eq!(state.players["bob"].seat_request(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_attacker_is_picked_by_trump() -> anyhow::Result<()> {
+        let mut state = game_with_players(&["alice", "bob", "carol"]);
+        // Spade ace, (3 * 13 + 14) % 3 == 2
+        state.trump = Some(Card::new(TRUMP_IDX, "sa".into()));
+        state.init_roles()?;
+        assert_eq!(state.players["carol"].role(), Some(Role::Attacker));
+        assert_eq!(state.players["alice"].role(), Some(Role::Defender));
+        assert_eq!(state.players["bob"].role(), Some(Role::CoAttacker));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stake_is_decided_per_game() {
+        let mut state = game_with_players(&["alice", "bob"]);
+        state.players.get_mut("alice").unwrap().balance = 300;
+        state.players.get_mut("bob").unwrap().balance = 150;
+        assert_eq!(state.next_stake(), Some(100));
+        state.stake_rule = StakeRule::MinBalance;
+        assert_eq!(state.next_stake(), Some(150));
+
+        set_ready(&mut state, &["alice", "bob"]);
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert!(effect.start_game);
+        assert_eq!(state.stake, 150);
+    }
+
+    #[test]
+    fn test_refuse_to_start_if_stake_not_covered() {
+        let mut state = game_with_players(&["alice", "bob"]);
+        state.stake_rule = StakeRule::MinBalance;
+        state.players.get_mut("bob").unwrap().balance = 50;
+        assert_eq!(state.next_stake(), None);
+
+        set_ready(&mut state, &["alice", "bob"]);
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert!(!effect.start_game);
+        assert_eq!(state.stage, Stage::Waiting);
+        assert_eq!(effect.settles, vec![Settle::eject("bob")]);
+        assert!(effect.is_checkpoint);
+        assert!(matches!(
+            state.displays.as_slice(),
+            [Display::PlayerKicked { addr }] if addr == "bob"
+        ));
+        assert!(!state.players.contains_key("bob"));
+    }
+
+    #[test]
+    fn test_start_without_the_underfunded_players() {
+        let mut state = game_with_players(&["alice", "bob", "carol"]);
+        state.min_num_of_players = 2;
+        state.players.get_mut("carol").unwrap().balance = 50;
+
+        set_ready(&mut state, &["alice", "bob", "carol"]);
+        let mut effect = Effect::default();
+        state.try_start_game(&mut effect);
+        assert!(effect.start_game);
+        assert_eq!(effect.settles, vec![Settle::eject("carol")]);
+        assert_eq!(state.stage, Stage::Shuffling);
+        assert_eq!(state.num_of_players, 2);
+    }
+
+    #[test]
+    fn test_shame_rule_multiplies_the_loss() -> anyhow::Result<()> {
+        let addrs = ["alice", "bob"];
+        let mut state = game_with_players(&addrs);
+        state.shame_rule = Some(ShameRule {
+            kinds: vec!["6".into()],
+            max_multiplier: 3,
+        });
+        state.players.get_mut("bob").unwrap().balance = 250;
+        state.players.get_mut("bob").unwrap().card_idxs = vec![3, 7, 9];
+        state.deck_offset = DECK_LEN;
+        let mut effect = Effect::default();
+        finish_game(&mut state, &addrs, &mut effect);
+        assert_eq!(state.stage, Stage::RevealingHands);
+        assert_eq!(effect.reveals.len(), 1);
+        assert_eq!(effect.reveals[0].indexes, vec![3, 7, 9]);
+        assert!(effect.settles.is_empty());
+
+        let mut effect = Effect::default();
+        effect.revealed.insert(
+            state.random_id,
+            HashMap::from([(3, "h6".into()), (7, "s6".into()), (9, "da".into())]),
+        );
+        let event = Event::SecretsReady {
+            random_ids: vec![state.random_id],
+        };
+        state.handle_event(&mut effect, event)?;
+        assert_eq!(state.stage, Stage::EndOfGame);
+        assert_eq!(state.players["bob"].known_cards().len(), 3);
+        // 3x the bet, limited by the balance of bob
+        assert_eq!(
+            effect.settles,
+            vec![Settle::add("alice", 250), Settle::sub("bob", 250)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_shame_loss_is_capped_by_the_balance() -> anyhow::Result<()> {
+        let addrs = ["alice", "bob", "carol"];
+        let mut state = game_with_players(&addrs);
+        state.shame_rule = Some(ShameRule {
+            kinds: vec!["6".into()],
+            max_multiplier: 4,
+        });
+        // Carol covers the stake, but not four times of it
+        let carol = state.players.get_mut("carol").unwrap();
+        carol.balance = 120;
+        carol.card_idxs = vec![3, 7, 9];
+        state.deck_offset = DECK_LEN;
+        let mut effect = Effect::default();
+        finish_game(&mut state, &addrs, &mut effect);
+
+        let mut effect = Effect::default();
+        effect.revealed.insert(
+            state.random_id,
+            HashMap::from([(3, "h6".into()), (7, "s6".into()), (9, "d6".into())]),
+        );
+        let event = Event::SecretsReady {
+            random_ids: vec![state.random_id],
+        };
+        state.handle_event(&mut effect, event)?;
+        assert_eq!(state.loss_multiplier(), 4);
+        assert!(effect.settles.contains(&Settle::sub("carol", 120)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_remaining_hands_are_revealed_before_settle() -> anyhow::Result<()> {
+        let addrs = ["alice", "bob", "carol"];
+        let mut state = game_with_players(&addrs);
+        state.players.get_mut("carol").unwrap().card_idxs = vec![5, 12];
+        state.deck_offset = DECK_LEN;
+        let mut effect = Effect::default();
+        finish_game(&mut state, &addrs, &mut effect);
+        assert_eq!(state.stage, Stage::RevealingHands);
+        assert_eq!(effect.reveals[0].indexes, vec![5, 12]);
+        assert!(effect.settles.is_empty());
+
+        let mut effect = Effect::default();
+        effect
+            .revealed
+            .insert(state.random_id, HashMap::from([(5, "c9".into())]));
+        let event = Event::SecretsReady {
+            random_ids: vec![state.random_id],
+        };
+        state.handle_event(&mut effect, event.clone())?;
+        assert_eq!(state.stage, Stage::RevealingHands);
+        assert_eq!(effect.reveals[0].indexes, vec![12]);
+
+        let mut effect = Effect::default();
+        effect.revealed.insert(
+            state.random_id,
+            HashMap::from([(5, "c9".into()), (12, "hk".into())]),
+        );
+        state.handle_event(&mut effect, event)?;
+        assert_eq!(state.stage, Stage::EndOfGame);
+        assert_eq!(effect.settles.len(), 2);
+        assert!(state.displays.iter().any(|d| matches!(
+            d,
+            Display::HandRevealed { addr, cards } if addr == "carol" && cards.len() == 2
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shame_rule_multiplier() {
+        let rule = ShameRule {
+            kinds: vec!["6".into(), "7".into()],
+            max_multiplier: 2,
+        };
+        assert_eq!(rule.multiplier(&[]), 1);
+        assert_eq!(rule.multiplier(&[Card::new(0, "ha".into())]), 1);
+        assert_eq!(rule.multiplier(&[Card::new(0, "h6".into())]), 2);
+        let cards = [Card::new(0, "h6".into()), Card::new(1, "s7".into())];
+        assert_eq!(rule.multiplier(&cards), 2);
     }
 }
@@ -0,0 +1,194 @@
+//! The game states shared by the tests.  It lives under `misc` to
+//! set up the private fields of the players.
+
+use std::collections::HashMap;
+
+use super::*;
+
+/// A table seated by `addrs`, each with the balance of the bet.
+pub fn game_with_players(addrs: &[&str]) -> Durak {
+    let mut state = Durak {
+        num_of_players: addrs.len(),
+        entrant_addrs: addrs.iter().map(|a| a.to_string()).collect(),
+        num_of_seats: addrs.len(),
+        min_num_of_players: addrs.len(),
+        bet_amount: 100,
+        stake: 100,
+        ..Default::default()
+    };
+    for (i, addr) in addrs.iter().enumerate() {
+        let p = Player::new(addr.to_string(), i as u16, 100);
+        state.players.insert(addr.to_string(), p);
+    }
+    state
+}
+
+/// Rank the players by the order of `addrs`, the last one is the
+/// durak, then end the game.
+pub fn finish_game(state: &mut Durak, addrs: &[&str], effect: &mut Effect) {
+    state.stage = Stage::Acting;
+    for (i, addr) in addrs.iter().take(addrs.len() - 1).enumerate() {
+        state.players.get_mut(*addr).unwrap().set_rank(i as u8);
+    }
+    state.num_of_finished = addrs.len() - 1;
+    state.maybe_end_game(effect).unwrap();
+}
+
+/// A game ended by the ranks in the order of `addrs`.
+pub fn ended_game(addrs: &[&str], effect: &mut Effect) -> Durak {
+    let mut state = game_with_players(addrs);
+    finish_game(&mut state, addrs, effect);
+    state
+}
+
+/// All cards of the deck, indexed in order.
+pub fn all_cards() -> Vec<Card> {
+    let mut cards = vec![];
+    for suit in ["s", "h", "d", "c"] {
+        for kind in ["6", "7", "8", "9", "t", "j", "q", "k", "a"] {
+            cards.push(Card::new(cards.len(), format!("{}{}", suit, kind)));
+        }
+    }
+    cards
+}
+
+/// Build an attack in each of the four states.
+pub fn attack_in_state(state: usize, open: &Card, close: &Card) -> Attack {
+    match state {
+        0 => Attack::ConfirmOpen { open_idx: open.idx },
+        1 => Attack::Open { open: open.clone() },
+        2 => Attack::ConfirmClose {
+            open: open.clone(),
+            close_idx: close.idx,
+        },
+        _ => Attack::Closed {
+            open: open.clone(),
+            close: close.clone(),
+        },
+    }
+}
+
+/// Mark the players of `addrs` ready.
+pub fn set_ready(state: &mut Durak, addrs: &[&str]) {
+    for addr in addrs {
+        state.players.get_mut(*addr).unwrap().ready = true;
+    }
+}
+
+/// An effect handled at `timestamp`.
+pub fn effect_at(timestamp: u64) -> Effect {
+    Effect {
+        timestamp,
+        ..Default::default()
+    }
+}
+
+/// The event of `addr` joining at `position`.
+pub fn sync(addr: &str, position: u16, balance: u64) -> Event {
+    Event::Sync {
+        new_players: vec![PlayerJoin {
+            addr: addr.into(),
+            position,
+            balance,
+            access_version: 1,
+            verify_key: "".into(),
+        }],
+        new_servers: vec![],
+        transactor_addr: "".into(),
+        access_version: 1,
+    }
+}
+
+/// A table waiting for the players of `addrs` to get ready.
+pub fn waiting_table(addrs: &[&str], num_of_seats: usize, min_num_of_players: usize) -> Durak {
+    let mut state = game_with_players(addrs);
+    state.num_of_seats = num_of_seats;
+    state.min_num_of_players = min_num_of_players;
+    state
+}
+
+/// A three-player game in the acting stage, each with six cards
+/// and no attacks yet.
+pub fn acting_game() -> Durak {
+    let mut state = game_with_players(&["alice", "bob", "carol"]);
+    state.stage = Stage::Acting;
+    state.deck_offset = 18;
+    for (i, addr) in ["alice", "bob", "carol"].iter().enumerate() {
+        let p = state.players.get_mut(*addr).unwrap();
+        p.card_idxs = (i * 6..i * 6 + 6).collect();
+    }
+    state.trump = Some(Card::new(TRUMP_IDX, "h2".into()));
+    state.init_roles().unwrap();
+    state.update_attack_space().unwrap();
+    state
+}
+
+/// The first violated invariant, empty if there's none.
+pub fn violated(state: &Durak) -> String {
+    match state.check_invariants() {
+        Err(e) => e.to_string(),
+        Ok(()) => "".into(),
+    }
+}
+
+/// An acting game where the attacker has attacked with a six, and
+/// the defender is able to forward it with another six.
+pub fn forwarding_game() -> (Durak, Card) {
+    let mut state = acting_game();
+    let att = state.get_player_by_role_mut(Role::Attacker).unwrap();
+    let idx = att.take_card(att.card_idxs[0]).unwrap();
+    state.attacks = vec![Attack::Open {
+        open: Card::new(idx, "s6".into()),
+    }];
+    let def = state.get_player_by_role(Role::Defender).unwrap();
+    let card = Card::new(def.card_idxs[0], "d6".into());
+    state.update_attack_space().unwrap();
+    (state, card)
+}
+
+/// Handle the rejected action, the state must be untouched.
+pub fn rejected(state: &mut Durak, sender: &str, action: Action) -> HandleError {
+    let snapshot = state.try_to_vec().unwrap();
+    let mut effect = Effect::default();
+    let res = state.handle_action(&mut effect, sender.into(), action);
+    assert_eq!(state.try_to_vec().unwrap(), snapshot);
+    res.unwrap_err()
+}
+
+/// Forward with the rejected `card`, the state must be untouched.
+pub fn forward_rejected(state: &mut Durak, sender: &str, card: Card) -> HandleError {
+    rejected(state, sender, Action::Forward { card })
+}
+
+/// Whether the round is ended by the defender taking the cards.
+pub fn round_ended(state: &Durak) -> bool {
+    state.displays.iter().any(|d| {
+        matches!(
+            d,
+            Display::RoundEnded {
+                attack_succeed: true
+            }
+        )
+    })
+}
+
+/// The players dealt cards, in order.
+pub fn dealt_addrs(state: &Durak) -> Vec<String> {
+    state
+        .displays
+        .iter()
+        .filter_map(|d| match d {
+            Display::DealCards { addr, .. } => Some(addr.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// An effect with `cards` revealed.
+pub fn revealed(state: &Durak, cards: &[&Card]) -> Effect {
+    let revealed = cards.iter().map(|c| (c.idx, c.value.clone())).collect();
+    Effect {
+        revealed: HashMap::from([(state.random_id, revealed)]),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,108 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    misc::{Attack, Role, Stage, DECK_LEN},
+    Durak,
+};
+
+/// A player as seen by spectators.  Only the size of the hand is
+/// exposed, along with the cards which are already public.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+pub struct SpectatorPlayer {
+    pub addr: String,
+    pub position: u16,
+    pub role: Option<Role>,
+    pub rank: Option<u8>,
+    pub hand_size: usize,
+    pub known_cards: Vec<String>,
+}
+
+/// An attack as seen by spectators.  Cards waiting to be revealed
+/// are shown as pending, without their indexes.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+pub enum SpectatorAttack {
+    PendingOpen,
+    Open { open: String },
+    PendingClose { open: String },
+    Closed { open: String, close: String },
+}
+
+impl From<&Attack> for SpectatorAttack {
+    fn from(attack: &Attack) -> Self {
+        match attack {
+            Attack::ConfirmOpen { .. } => Self::PendingOpen,
+            Attack::Open { open } => Self::Open {
+                open: open.value.clone(),
+            },
+            Attack::ConfirmClose { open, .. } => Self::PendingClose {
+                open: open.value.clone(),
+            },
+            Attack::Closed { open, close } => Self::Closed {
+                open: open.value.clone(),
+                close: close.value.clone(),
+            },
+        }
+    }
+}
+
+/// The public projection of the game state, safe to be streamed to
+/// observers and lobby previews.
+///
+/// This is a serialized schema shared with the clients, new fields
+/// must be appended to the end.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+pub struct SpectatorView {
+    pub stage: Stage,
+    pub num_of_players: usize,
+    pub bet_amount: u64,
+    pub trump: Option<String>,
+    pub deck_size: usize,
+    pub discard_size: usize,
+    pub players: Vec<SpectatorPlayer>,
+    pub attacks: Vec<SpectatorAttack>,
+    pub timeout: u64,
+    pub stake: u64,
+}
+
+impl Durak {
+    /// Project the game state into a view without any card indexes.
+    pub fn spectator_view(&self) -> SpectatorView {
+        let mut players: Vec<SpectatorPlayer> = self
+            .players
+            .values()
+            .map(|p| SpectatorPlayer {
+                addr: p.addr(),
+                position: p.position(),
+                role: p.role(),
+                rank: p.rank(),
+                hand_size: p.card_idxs().len(),
+                known_cards: p.known_cards().iter().map(|c| c.value.clone()).collect(),
+            })
+            .collect();
+        players.sort_by_key(|p| p.position);
+
+        // Cards on the table, including those waiting to be revealed
+        let num_on_table: usize = self
+            .attacks
+            .iter()
+            .map(|a| match a {
+                Attack::ConfirmOpen { .. } | Attack::Open { .. } => 1,
+                Attack::ConfirmClose { .. } | Attack::Closed { .. } => 2,
+            })
+            .sum();
+        let num_in_hands: usize = players.iter().map(|p| p.hand_size).sum();
+
+        SpectatorView {
+            stage: self.stage,
+            num_of_players: self.num_of_players,
+            bet_amount: self.bet_amount,
+            trump: self.trump.as_ref().map(|c| c.value.clone()),
+            deck_size: DECK_LEN.saturating_sub(self.deck_offset),
+            discard_size: self.deck_offset.saturating_sub(num_on_table + num_in_hands),
+            players,
+            attacks: self.attacks.iter().map(SpectatorAttack::from).collect(),
+            timeout: self.timeout,
+            stake: self.stake,
+        }
+    }
+}